@@ -1,78 +1,106 @@
 use serenity::all::Interaction;
+use serenity::builder::{CreateAutocompleteResponse, CreateInteractionResponse};
 use serenity::async_trait;
-use serenity::builder::{CreateInteractionResponseMessage, CreateInteractionResponse};
 use serenity::model::gateway::Ready;
-use serenity::model::prelude::{GuildId, Message, MessageUpdateEvent};
+use serenity::model::prelude::{
+    ChannelId, Guild, GuildChannel, GuildId, Message, MessageId,
+    MessageUpdateEvent, PartialGuildChannel, UnavailableGuild
+};
 use serenity::prelude::*;
 
 use bot_data::user_message_cache::UserMessageData;
+use bot_data::settings::{SettingsData, get_channel_parents};
 
 use bot_data::config::ConfigData;
+use commands::response::{respond, update};
+use commands::slash_command::SlashContext;
+use commands::CommandRegistryData;
+use commands::registry::{get_scopes, sync_commands};
 
 pub struct DiscordEventHandler;
 
 #[async_trait]
 impl EventHandler for DiscordEventHandler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        let config = {
+        let (bot_config, registry) = {
             let data_read = ctx.data.read().await;
 
-            data_read.get::<ConfigData>().expect("Expected Config").clone()
+            (
+                data_read.get::<ConfigData>().expect("Expected ConfigData").clone(),
+                data_read.get::<CommandRegistryData>().expect("Expected CommandRegistryData").clone()
+            )
         };
 
-        if let Interaction::Command(command) = interaction {
-            let response = match command.data.name.as_str() {
-                "cat" => Some(commands::slash_cat::run(&command.data.options()).await),
-                "dog" => Some(commands::slash_dog::run(&command.data.options()).await),
-                "scramblr" => {
-                    // get user message cache
-                    let user_message_cache = {
-                        let data_read = ctx.data.read().await;
-
-                        data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
-                    }.read().await.clone();
-
-                    Some(commands::slash_scramblr::run(
-                        &command.user,
-                        &user_message_cache,
-                        &command.data.options(),
-                        &config
-                    ).await)
-                },
-                _ => None,
-            };
-
-            if let Some(content) = response {
-                let data = CreateInteractionResponseMessage::new().content(content);
-                let builder = CreateInteractionResponse::Message(data);
-
-                if let Err(response_error) = command.create_response(&ctx.http, builder).await {
+        let defer_after = bot_config.get_commands_config().get_defer_after();
+        let slash_ctx = SlashContext::new(&ctx, bot_config);
+
+        match interaction {
+            Interaction::Command(command) => {
+                let slash_command = match registry.get_slash_command(&command.data.name) {
+                    Some(slash_command) => slash_command,
+                    None => {
+                        println!("Unknown slash command: {}", command.data.name);
+                        return;
+                    }
+                };
+
+                let result = respond(
+                    &ctx.http,
+                    &command,
+                    defer_after,
+                    slash_command.is_ephemeral(),
+                    slash_command.run(&slash_ctx, &command)
+                ).await;
+
+                if let Err(response_error) = result {
                     println!("Cannot respond to slash command: {}", response_error);
                 }
-            }
-        }
-    }
-
-    async fn message(&self, ctx: Context, msg: Message) {
-        let msgs_lock = {
-            let data_read = ctx.data.read().await;
+            },
+            Interaction::Autocomplete(command) => {
+                let choices = match registry.get_slash_command(&command.data.name) {
+                    Some(slash_command) => slash_command.autocomplete(&slash_ctx, &command).await,
+                    None => Vec::new()
+                };
 
-            data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
-        };
+                let response = CreateInteractionResponse::Autocomplete(
+                    CreateAutocompleteResponse::new().set_choices(choices)
+                );
 
-        let config = {
-            let data_read = ctx.data.read().await;
+                if let Err(response_error) = command.create_response(&ctx.http, response).await {
+                    println!("Cannot respond to autocomplete: {}", response_error);
+                }
+            },
+            Interaction::Component(component) => {
+                // custom ids start with the name of the command that sent them
+                let (name, data) = component.data.custom_id.split_once(':').unwrap_or((&component.data.custom_id, ""));
 
-            data_read.get::<ConfigData>().expect("Expected Config").clone()
-        };
+                let slash_command = match registry.get_slash_command(name) {
+                    Some(slash_command) => slash_command,
+                    None => {
+                        println!("Unknown component: {}", component.data.custom_id);
+                        return;
+                    }
+                };
 
-        {
-            let mut cache = msgs_lock.write().await;
+                let result = update(
+                    &ctx.http,
+                    &component,
+                    defer_after,
+                    slash_command.handle_component(&slash_ctx, &component, data)
+                ).await;
 
-            cache.add_or_update_msg(&msg, &config);
+                if let Err(response_error) = result {
+                    println!("Cannot respond to component: {}", response_error);
+                }
+            },
+            _ => {}
         }
     }
 
+    async fn message(&self, ctx: Context, msg: Message) {
+        cache_user_message(&ctx, &Some(msg)).await;
+    }
+
     async fn message_update(
         &self,
         ctx: Context,
@@ -83,73 +111,173 @@ impl EventHandler for DiscordEventHandler {
         cache_user_message(&ctx, &new_message).await;
     }
 
-    async fn ready(&self, ctx: Context, ready: Ready) {
-        let bot_config = {
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        _guild_id: Option<GuildId>
+    ) {
+        uncache_deleted_messages(&ctx, channel_id, &[deleted_message_id]).await;
+    }
+
+    async fn message_delete_bulk(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        multiple_deleted_messages_ids: Vec<MessageId>,
+        _guild_id: Option<GuildId>
+    ) {
+        uncache_deleted_messages(&ctx, channel_id, &multiple_deleted_messages_ids).await;
+    }
+
+    async fn channel_delete(&self, ctx: Context, channel: GuildChannel, _messages: Option<Vec<Message>>) {
+        uncache_channel(&ctx, channel.id).await;
+    }
+
+    async fn thread_delete(&self, ctx: Context, thread: PartialGuildChannel, _full_thread_data: Option<GuildChannel>) {
+        uncache_channel(&ctx, thread.id).await;
+    }
+
+    async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: Option<bool>) {
+        let msgs_lock = {
             let data_read = ctx.data.read().await;
 
-            data_read.get::<ConfigData>().expect("Expected ConfigData").clone()
+            data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
+        };
+
+        let channel_ids = guild.channels.keys()
+            .chain(guild.threads.iter().map(|thread| &thread.id))
+            .map(|channel_id| channel_id.get())
+            .collect::<Vec<u64>>();
+
+        // tag messages cached before guilds were recorded
+        let mut cache = msgs_lock.write().await;
+
+        if let Err(cache_error) = cache.assign_guild(guild.id.get(), &channel_ids) {
+            println!("Could not assign cached messages to guild {}: {cache_error}", guild.id);
+        }
+    }
+
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, full: Option<Guild>) {
+        // the guild is only unavailable because of an outage,
+        // the bot hasn't left it
+        if incomplete.unavailable {
+            return;
+        }
+
+        // messages from before guilds were recorded
+        // can only be found by their channel
+        let channel_ids = match full {
+            Some(guild) => guild.channels.keys()
+                .chain(guild.threads.iter().map(|thread| &thread.id))
+                .map(|channel_id| channel_id.get())
+                .collect::<Vec<u64>>(),
+            None => Vec::new()
         };
 
-        if let Some(id) = bot_config.get_dev_guild_id() {
-            let guild_id = GuildId::new(*id);
+        uncache_guild(&ctx, incomplete.id, &channel_ids).await;
+    }
+
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        let (bot_config, registry) = {
+            let data_read = ctx.data.read().await;
 
-            let commands = guild_id.set_commands(
-                &ctx.http,
-                vec![
-                    commands::slash_cat::register(),
-                    commands::slash_dog::register(),
-                    commands::slash_scramblr::register()
-                ]
+            (
+                data_read.get::<ConfigData>().expect("Expected ConfigData").clone(),
+                data_read.get::<CommandRegistryData>().expect("Expected CommandRegistryData").clone()
             )
-            .await;
+        };
 
-            println!("registered guild commands: {commands:#?}");
-        } else {
-            println!("dev guild id missing or invalid, skipping command registration");
+        let commands_config = bot_config.get_commands_config();
+
+        if bot_config.get_dev_guild_id().is_none() {
+            println!("dev guild id missing or invalid, skipping guild command registration");
         }
 
-        /*let _guild_command = Command::create_global_application_command(&ctx.http, |command| {
-            commands::slash_cat::register(command);
-            commands::slash_dog::register(command)
-        })
-        .await;
-        
-        if let Ok(cmds) = ctx.http.get_global_application_commands().await {
-            for cmd in cmds {
-                if let Ok(_) = Command::delete_global_application_command(&ctx.http, cmd.id).await {
-                    println!("Removed global slash command w/id {}", cmd.id);
-                } else {
-                    println!("Could not remove global slash command w/id {}", cmd.id);
-                }
+        for scope in get_scopes(&bot_config) {
+            match sync_commands(&ctx.http, scope, registry.get_slash_commands(), commands_config.is_dry_run()).await {
+                Ok(plan) if commands_config.is_dry_run() => println!("dry run, not applied: {plan}"),
+                Ok(plan) => println!("synced {plan}"),
+                Err(e) => println!("Error while syncing {scope} commands: {e}")
             }
-        }*/
+        }
 
-    
         println!("{}: connected", ready.user.name);
     }
 }
 
 async fn cache_user_message(ctx: &Context, new_message: &Option<Message>) {
-    let msgs_lock = {
+    let (msgs_lock, settings_lock) = {
         let data_read = ctx.data.read().await;
 
-        data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
+        (
+            data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone(),
+            data_read.get::<SettingsData>().expect("Expected SettingsData").clone()
+        )
     };
 
-    println!("MSG UPDATE");
     if let Some(msg) = new_message {
+        let parent_ids = match msg.guild_id {
+            Some(guild_id) => get_channel_parents(&ctx.cache, guild_id, msg.channel_id),
+            None => Vec::new()
+        };
+
         {
+            let settings = settings_lock.read().await;
             let mut cache = msgs_lock.write().await;
 
-            let config = {
-                let data_read = ctx.data.read().await;
-    
-                data_read.get::<ConfigData>().expect("Expected Config").clone()
-            };
-
-            cache.add_or_update_msg(&msg, &config);
+            if let Err(cache_error) = cache.add_or_update_msg(msg, &settings, &parent_ids) {
+                println!("Could not cache message: {cache_error}");
+            }
         }
     } else {
         println!("not available");
     }   
-}
\ No newline at end of file
+}
+
+async fn uncache_deleted_messages(ctx: &Context, channel_id: ChannelId, message_ids: &[MessageId]) {
+    let msgs_lock = {
+        let data_read = ctx.data.read().await;
+
+        data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
+    };
+
+    let message_ids = message_ids.iter()
+        .map(|message_id| message_id.get())
+        .collect::<Vec<u64>>();
+
+    let mut cache = msgs_lock.write().await;
+
+    if let Err(cache_error) = cache.remove_deleted_messages(channel_id.get(), &message_ids) {
+        println!("Could not remove deleted messages from cache: {cache_error}");
+    }
+}
+
+async fn uncache_channel(ctx: &Context, channel_id: ChannelId) {
+    let msgs_lock = {
+        let data_read = ctx.data.read().await;
+
+        data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
+    };
+
+    let mut cache = msgs_lock.write().await;
+
+    if let Err(cache_error) = cache.remove_messages_in_channel(channel_id.get()) {
+        println!("Could not remove deleted channel from cache: {cache_error}");
+    }
+}
+
+async fn uncache_guild(ctx: &Context, guild_id: GuildId, channel_ids: &[u64]) {
+    let msgs_lock = {
+        let data_read = ctx.data.read().await;
+
+        data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
+    };
+
+    let mut cache = msgs_lock.write().await;
+
+    if let Err(cache_error) = cache.remove_messages_in_guild(guild_id.get(), channel_ids) {
+        println!("Could not remove left guild from cache: {cache_error}");
+    }
+}
@@ -1,15 +1,22 @@
 use std::{sync::Arc, collections::HashSet};
 
 use discord_event_handler::DiscordEventHandler;
-use serenity::{prelude::*, gateway::ShardManager, framework::StandardFramework, http::Http};
-
-use bot_data::{config::{Config, ConfigData}, encryption::{encrypt, decrypt}};
-use commands::{
-    utility::*,
-    fun::*
+use serenity::{
+    prelude::*,
+    gateway::ShardManager,
+    framework::{StandardFramework, standard::macros::hook},
+    http::Http,
+    model::channel::Message
 };
 
-use bot_data::user_message_cache::{UserMessageCache, UserMessageData};
+use bot_data::config::{Config, ConfigData};
+use commands::hybrid::{OwnerData, get_prefix, run_prefix_command};
+
+use bot_data::user_message_cache::{UserMessageCache, UserMessageData, spawn_flush_task};
+use bot_data::settings::{Settings, SettingsData, DEFAULT_SETTINGS_PATH};
+use commands::slash_mydata::{ExportCooldowns, ExportCooldownData};
+use commands::fetch_client::{FetchClient, FetchClientData};
+use commands::{CommandRegistry, CommandRegistryData};
 
 pub mod discord_event_handler;
 
@@ -21,7 +28,7 @@ impl TypeMapKey for ShardManagerContainer {
 
 #[tokio::main]
 async fn main() {
-    let config = Config::from_file("config.toml");
+    let config = Config::load(get_config_path().as_deref());
 
     match config {
         Ok(config) => {
@@ -47,14 +54,14 @@ async fn main() {
             };
 
             let framework = StandardFramework::new()
-                .group(&UTILITY_GROUP)
-                .group(&FUN_GROUP);
+                .normal_message(hybrid_command);
 
             framework.configure(|c| {
                 c.with_whitespace(false)
                  .on_mention(Some(bot_id))
-                 .prefixes(config.get_prefixes())
-                 .owners(owners)
+                 .prefixes(Vec::<String>::new())
+                 .dynamic_prefix(configured_prefix)
+                 .owners(owners.clone())
             });
 
             let intents = GatewayIntents::non_privileged()
@@ -68,17 +75,51 @@ async fn main() {
                 .await
                 .expect("Couldn't create client!");
             
-            client.cache.set_max_messages(256);
+            client.cache.set_max_messages(config.get_cache_config().get_discord_max_messages());
+
+            let mut user_message_cache = match UserMessageCache::from_config(&config) {
+                Ok(cache) => cache,
+                Err(cache_err) => panic!("Could not open message cache: {cache_err}")
+            };
+
+            if let Err(load_err) = user_message_cache.load_cache() {
+                println!("Could not load message cache, starting empty: {load_err}");
+            }
+
+            let settings = match Settings::open(Some(DEFAULT_SETTINGS_PATH)) {
+                Ok(settings) => settings,
+                Err(settings_err) => panic!("Could not load settings: {settings_err}")
+            };
+
+            let fetch_client = match FetchClient::from_config(config.get_http_config()) {
+                Ok(fetch_client) => Arc::new(fetch_client),
+                Err(fetch_err) => panic!("Could not create http client: {fetch_err}")
+            };
+
+            // the data map holds the only reference, so a reload
+            // dropping it also stops this registry's breed refreshes
+            let registry = Arc::new(CommandRegistry::from_config(&config));
+            registry.spawn_breed_refresh(fetch_client.clone());
+
+            let flush_interval = config.get_cache_config().get_flush_interval();
+            let msgs_lock = Arc::new(RwLock::new(user_message_cache));
 
             // DATA INSERTION
             {
                 let mut data = client.data.write().await;
 
-                data.insert::<UserMessageData>(Arc::new(RwLock::new(UserMessageCache::new())));
+                data.insert::<UserMessageData>(msgs_lock.clone());
+                data.insert::<SettingsData>(Arc::new(RwLock::new(settings)));
+                data.insert::<ExportCooldownData>(Arc::new(Mutex::new(ExportCooldowns::default())));
+                data.insert::<CommandRegistryData>(registry);
                 data.insert::<ConfigData>(Arc::new(config));
+                data.insert::<OwnerData>(Arc::new(owners));
+                data.insert::<FetchClientData>(fetch_client);
                 data.insert::<ShardManagerContainer>(client.shard_manager.clone());
             }
 
+            spawn_flush_task(msgs_lock.clone(), flush_interval);
+
             let shard_manager = client.shard_manager.clone();
 
             tokio::spawn(async move {
@@ -91,9 +132,46 @@ async fn main() {
             if let Err(err) = client.start().await {
                 println!("Error while running client: {err:?}")
             }
+
+            // the shards have stopped, so nothing more can be cached
+            let flush_result = msgs_lock.write().await.flush();
+
+            if let Err(flush_err) = flush_result {
+                println!("Could not save message cache: {flush_err}");
+            }
         },
         Err(config_error) => {
             println!("An error occurred while loading config: {config_error:#}")
         }
     }
-}
\ No newline at end of file
+}
+
+/// Matches the prefixes the message's guild has set, or else the
+/// ones in the current config, so they can change while running
+#[hook]
+async fn configured_prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    get_prefix(ctx, msg).await
+}
+
+/// Runs hybrid commands, as the framework has no command groups
+#[hook]
+async fn hybrid_command(ctx: &Context, msg: &Message) {
+    run_prefix_command(ctx, msg).await;
+}
+
+/// Returns the path given with `--config <path>` or `--config=<path>`
+fn get_config_path() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+
+    None
+}
@@ -1,35 +1,852 @@
 use std::sync::Arc;
 
 use serenity::prelude::TypeMapKey;
+use toml::{Table, Value};
+
+/// Default location of the config file
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Prefix of environment variables overriding config values.
+/// 
+/// `RITTOU_TOKEN` sets `token`, and `__` separates sections,
+/// so `RITTOU_CACHE__MAX_USER_MESSAGES` sets `max_user_messages`
+/// in the `[cache]` section. A number picks an entry of a list of
+/// tables, so `RITTOU_IMAGES__0__URL` sets `url` in the first
+/// `[[images]]` table. Only entries in the config file can be picked.
+pub const ENV_PREFIX: &str = "RITTOU_";
+
+/// Fields set by environment variables as given, never read as TOML,
+/// so a token like `123` or a path like `true` stays a string.
+/// Sections are separated by `__`, as in the variable names, and
+/// list indices are left out.
+const ENV_STRING_FIELDS: &[&str] = &[
+    "token",
+    "token_file",
+    "secret_key",
+    "secret_key_file",
+    "cache__backend",
+    "cache__path",
+    "cache__toml_path",
+    "images__description",
+    "images__link_pointer",
+    "images__attribution",
+    "images__fallback_dir"
+];
+
+/// Fields holding lists of strings, which environment variables may
+/// also set to a single bare value, so `!` is read as `["!"]`
+const ENV_LIST_FIELDS: &[&str] = &[
+    "prefixes",
+    "retired_secret_keys",
+    "images__fallback_urls"
+];
+
+/// Shortest secret key accepted, in bytes
+pub const MIN_SECRET_KEY_LEN: usize = 16;
 
 /// Errors that can occur with a config file
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigError {
     /// Returned when a config file could
     /// not be read from file
-    #[error("Could not read config file")]
-    ConfigReadError,
+    #[error("Could not read config file `{0}`: {1}")]
+    ConfigReadError(String, #[source] std::io::Error),
 
     /// Returned when a config file could
     /// not be parsed
-    #[error("Could not parse config file")]
-    ConfigParseError
+    #[error("Could not parse config file `{0}`: {1}")]
+    ConfigParseError(String, #[source] toml::de::Error),
+
+    /// Returned when an environment variable
+    /// could not be applied to the config
+    #[error("Invalid environment variable `{0}`: {1}")]
+    EnvVarError(String, String),
+
+    /// Returned when a `*_file` value could not be read
+    #[error("Could not read `{0}` from `{1}`: {2}")]
+    SecretFileError(String, String, #[source] std::io::Error),
+
+    /// Returned when a config value is
+    /// out of its allowed range
+    #[error("Invalid config value for `{0}`: {1}")]
+    InvalidValue(String, String)
 }
 
+/// Most tries `/scramblr` may be configured to make
+pub const MAX_SCRAMBLR_TRIES: usize = 1000;
+
 /// A collection of configuration values
 /// for Yukimi
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 pub struct Config {
+    /// File the config was read from, if any
+    #[serde(skip)]
+    path: Option<String>,
+
     /// Login token for Discord
+    #[serde(default)]
     token: String,
 
+    /// File to read `token` from instead, e.g. a container secret
+    #[serde(default)]
+    token_file: Option<String>,
+
     /// Command prefixes
+    #[serde(default)]
     prefixes: Vec<String>,
 
     #[serde(default)]
     dev_guild_id: Option<u64>,
 
+    #[serde(default)]
     secret_key: String,
+
+    /// File to read `secret_key` from instead
+    #[serde(default)]
+    secret_key_file: Option<String>,
+
+    /// Secret keys replaced by `secret_key`, kept so messages
+    /// encrypted with them stay readable until the cache
+    /// has been rotated to the current key
+    #[serde(default)]
+    retired_secret_keys: Vec<String>,
+
+    /// Message cache settings
+    #[serde(default)]
+    cache: CacheConfig,
+
+    /// `/scramblr` settings
+    #[serde(default)]
+    scramblr: ScramblrConfig,
+
+    /// Slash command registration settings
+    #[serde(default)]
+    commands: CommandsConfig,
+
+    /// Settings for requests to outside APIs
+    #[serde(default)]
+    http: HttpConfig,
+
+    /// What image commands do while their API is down
+    #[serde(default)]
+    fallback: FallbackConfig,
+
+    /// Random image commands, like `/cat`, each
+    /// given as an `[[images]]` table
+    #[serde(default = "default_images")]
+    images: Vec<ImageProviderConfig>,
+}
+
+/// Storage backends available to the message cache
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// Kept in memory only, lost on shutdown
+    Memory,
+
+    /// Kept in memory and written to a TOML file on save
+    Toml,
+
+    /// Written to an embedded PoloDB database as it changes
+    #[default]
+    #[serde(rename = "polodb")]
+    PoloDb,
+}
+
+/// The `[cache]` section of the config file
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct CacheConfig {
+    /// Where cached messages are stored
+    #[serde(default)]
+    backend: CacheBackend,
+
+    /// File the backend stores messages in. Each
+    /// backend has its own default location
+    #[serde(default)]
+    path: Option<String>,
+
+    /// TOML cache file imported into an empty PoloDB
+    /// database, e.g. after moving off the `toml` backend
+    #[serde(default)]
+    toml_path: Option<String>,
+
+    /// Seconds between background flushes of the cache
+    #[serde(default = "default_flush_interval_secs")]
+    flush_interval_secs: u64,
+
+    /// Number of unsaved writes that triggers
+    /// a flush before the interval is up
+    #[serde(default = "default_flush_after_writes")]
+    flush_after_writes: usize,
+
+    /// Messages kept per user, across all channels
+    #[serde(default = "default_max_user_messages")]
+    max_user_messages: usize,
+
+    /// Messages kept per guild, unlimited if unset
+    #[serde(default)]
+    max_guild_messages: Option<usize>,
+
+    /// Seconds messages are kept for, forever if unset
+    #[serde(default)]
+    max_age_secs: Option<u64>,
+
+    /// Total bytes of encrypted messages kept, unlimited if unset
+    #[serde(default)]
+    max_bytes: Option<usize>,
+
+    /// Fewest words a message needs to be cached
+    #[serde(default = "default_min_words")]
+    min_words: usize,
+
+    /// Messages kept per channel by serenity's own cache
+    #[serde(default = "default_discord_max_messages")]
+    discord_max_messages: usize,
+}
+
+fn default_flush_interval_secs() -> u64 { 300 }
+
+fn default_flush_after_writes() -> usize { 100 }
+
+fn default_max_user_messages() -> usize { crate::user_message_cache::DEFAULT_MAX_USER_MESSAGES }
+
+fn default_min_words() -> usize { 3 }
+
+fn default_discord_max_messages() -> usize { 256 }
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackend::default(),
+            path: None,
+            toml_path: None,
+            flush_interval_secs: default_flush_interval_secs(),
+            flush_after_writes: default_flush_after_writes(),
+            max_user_messages: default_max_user_messages(),
+            max_guild_messages: None,
+            max_age_secs: None,
+            max_bytes: None,
+            min_words: default_min_words(),
+            discord_max_messages: default_discord_max_messages()
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Returns the selected storage backend
+    pub fn get_backend(&self) -> CacheBackend { self.backend }
+
+    /// Returns the configured storage path, if any
+    pub fn get_path(&self) -> Option<&str> { self.path.as_deref() }
+
+    /// Returns the TOML cache file to import into an empty database, if set
+    pub fn get_toml_path(&self) -> Option<&str> { self.toml_path.as_deref() }
+
+    /// Returns the time between background flushes
+    pub fn get_flush_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.flush_interval_secs)
+    }
+
+    /// Returns how many unsaved writes trigger an early flush
+    pub fn get_flush_after_writes(&self) -> usize { self.flush_after_writes }
+
+    /// Returns how many messages are kept per user
+    pub fn get_max_user_messages(&self) -> usize { self.max_user_messages }
+
+    /// Returns how many messages are kept per guild, if limited
+    pub fn get_max_guild_messages(&self) -> Option<usize> { self.max_guild_messages }
+
+    /// Returns how long messages are kept, if limited
+    pub fn get_max_age(&self) -> Option<std::time::Duration> {
+        self.max_age_secs.map(std::time::Duration::from_secs)
+    }
+
+    /// Returns how many bytes of messages are kept, if limited
+    pub fn get_max_bytes(&self) -> Option<usize> { self.max_bytes }
+
+    /// Returns the fewest words a message needs to be cached
+    pub fn get_min_words(&self) -> usize { self.min_words }
+
+    /// Returns how many messages serenity keeps per channel
+    pub fn get_discord_max_messages(&self) -> usize { self.discord_max_messages }
+
+    /// Keeps the values of `old` that only take effect on
+    /// startup, returning a warning for each one that changed
+    fn keep_restart_only(&mut self, old: &Self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.backend != old.backend {
+            warnings.push(restart_warning("cache.backend"));
+            self.backend = old.backend;
+        }
+
+        if self.path != old.path {
+            warnings.push(restart_warning("cache.path"));
+            self.path = old.path.clone();
+        }
+
+        if self.toml_path != old.toml_path {
+            warnings.push(restart_warning("cache.toml_path"));
+            self.toml_path = old.toml_path.clone();
+        }
+
+        if self.flush_interval_secs != old.flush_interval_secs {
+            warnings.push(restart_warning("cache.flush_interval_secs"));
+            self.flush_interval_secs = old.flush_interval_secs;
+        }
+
+        warnings
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_user_messages == 0 {
+            return Err(invalid("cache.max_user_messages", "must be at least 1"));
+        }
+
+        if self.max_guild_messages == Some(0) {
+            return Err(invalid("cache.max_guild_messages", "must be at least 1, or unset for no limit"));
+        }
+
+        if self.max_age_secs == Some(0) {
+            return Err(invalid("cache.max_age_secs", "must be at least 1, or unset for no limit"));
+        }
+
+        if self.max_bytes == Some(0) {
+            return Err(invalid("cache.max_bytes", "must be at least 1, or unset for no limit"));
+        }
+
+        if self.min_words == 0 {
+            return Err(invalid("cache.min_words", "must be at least 1"));
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[scramblr]` section of the config file
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ScramblrConfig {
+    /// Fewest cached messages each user needs
+    #[serde(default = "default_min_messages")]
+    min_messages: usize,
+
+    /// Message pairs tried before giving up
+    #[serde(default = "default_max_tries")]
+    max_tries: usize,
+}
+
+fn default_min_messages() -> usize { 4 }
+
+fn default_max_tries() -> usize { 25 }
+
+impl Default for ScramblrConfig {
+    fn default() -> Self {
+        Self {
+            min_messages: default_min_messages(),
+            max_tries: default_max_tries()
+        }
+    }
+}
+
+impl ScramblrConfig {
+    /// Returns the fewest cached messages each user needs
+    pub fn get_min_messages(&self) -> usize { self.min_messages }
+
+    /// Returns how many message pairs are tried before giving up
+    pub fn get_max_tries(&self) -> usize { self.max_tries }
+
+    fn validate(&self, cache: &CacheConfig) -> Result<(), ConfigError> {
+        if self.min_messages == 0 {
+            return Err(invalid("scramblr.min_messages", "must be at least 1"));
+        }
+
+        if self.min_messages > cache.max_user_messages {
+            return Err(invalid("scramblr.min_messages", "must not be more than cache.max_user_messages"));
+        }
+
+        if self.max_tries == 0 || self.max_tries > MAX_SCRAMBLR_TRIES {
+            return Err(invalid("scramblr.max_tries", &format!("must be between 1 and {MAX_SCRAMBLR_TRIES}")));
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[commands]` section of the config file
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct CommandsConfig {
+    /// Register slash commands globally, as well as in the dev guild
+    #[serde(default)]
+    global: bool,
+
+    /// Only print the changes registering would make
+    #[serde(default)]
+    dry_run: bool,
+
+    /// How long a slash command may run before its reply is deferred
+    #[serde(default = "default_defer_after_ms")]
+    defer_after_ms: u64,
+}
+
+/// Discord's deadline for replying to an interaction
+const INTERACTION_DEADLINE_MS: u64 = 3000;
+
+fn default_defer_after_ms() -> u64 { 1500 }
+
+impl Default for CommandsConfig {
+    fn default() -> Self {
+        Self {
+            global: false,
+            dry_run: false,
+            defer_after_ms: default_defer_after_ms()
+        }
+    }
+}
+
+impl CommandsConfig {
+    /// Returns `true` if slash commands are registered globally
+    pub fn is_global(&self) -> bool { self.global }
+
+    /// Returns `true` if registration should only print its plan
+    pub fn is_dry_run(&self) -> bool { self.dry_run }
+
+    /// Returns how long a slash command may run before its reply is deferred
+    pub fn get_defer_after(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.defer_after_ms)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        // leave time for the deferral itself to reach discord
+        if self.defer_after_ms >= INTERACTION_DEADLINE_MS - 500 {
+            return Err(invalid("commands.defer_after_ms", &format!("must be less than {}", INTERACTION_DEADLINE_MS - 500)));
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[http]` section of the config file
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct HttpConfig {
+    /// Longest a whole request may take
+    #[serde(default = "default_timeout_secs")]
+    timeout_secs: u64,
+
+    /// Longest connecting may take
+    #[serde(default = "default_connect_timeout_secs")]
+    connect_timeout_secs: u64,
+
+    /// Times a request is retried after a 5xx or 429 response
+    #[serde(default = "default_max_retries")]
+    max_retries: u32,
+
+    /// Wait before the first retry, doubled for each one after
+    #[serde(default = "default_retry_backoff_ms")]
+    retry_backoff_ms: u64,
+}
+
+fn default_timeout_secs() -> u64 { 10 }
+
+fn default_connect_timeout_secs() -> u64 { 5 }
+
+fn default_max_retries() -> u32 { 2 }
+
+fn default_retry_backoff_ms() -> u64 { 250 }
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: default_timeout_secs(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            max_retries: default_max_retries(),
+            retry_backoff_ms: default_retry_backoff_ms()
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Returns the longest a whole request may take
+    pub fn get_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_secs)
+    }
+
+    /// Returns the longest connecting may take
+    pub fn get_connect_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    /// Returns how many times a failed request is retried
+    pub fn get_max_retries(&self) -> u32 { self.max_retries }
+
+    /// Returns the wait before the first retry
+    pub fn get_retry_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.retry_backoff_ms)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.timeout_secs == 0 {
+            return Err(invalid("http.timeout_secs", "must be at least 1"));
+        }
+
+        if self.connect_timeout_secs == 0 {
+            return Err(invalid("http.connect_timeout_secs", "must be at least 1"));
+        }
+
+        if self.max_retries > 10 {
+            return Err(invalid("http.max_retries", "must not be more than 10"));
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[fallback]` section of the config file
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct FallbackConfig {
+    /// Images kept from successful fetches, per command,
+    /// to serve while the command's API is down
+    #[serde(default = "default_pool_size")]
+    pool_size: usize,
+
+    /// Failed fetches in a row before an API is skipped
+    #[serde(default = "default_failure_threshold")]
+    failure_threshold: u32,
+
+    /// How long a failing API is skipped for, in seconds
+    #[serde(default = "default_cooldown_secs")]
+    cooldown_secs: u64,
+}
+
+fn default_pool_size() -> usize { 50 }
+
+fn default_failure_threshold() -> u32 { 3 }
+
+fn default_cooldown_secs() -> u64 { 60 }
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            pool_size: default_pool_size(),
+            failure_threshold: default_failure_threshold(),
+            cooldown_secs: default_cooldown_secs()
+        }
+    }
+}
+
+impl FallbackConfig {
+    /// Returns how many fetched images are kept per command
+    pub fn get_pool_size(&self) -> usize { self.pool_size }
+
+    /// Returns how many failed fetches in a row skip an API
+    pub fn get_failure_threshold(&self) -> u32 { self.failure_threshold }
+
+    /// Returns how long a failing API is skipped for
+    pub fn get_cooldown(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cooldown_secs)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.failure_threshold == 0 {
+            return Err(invalid("fallback.failure_threshold", "must be at least 1"));
+        }
+
+        Ok(())
+    }
+}
+
+/// An `[[images]]` table, describing a command that
+/// posts a random image from a JSON API
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ImageProviderConfig {
+    /// Name of the command, e.g. `cat`
+    name: String,
+
+    /// Description shown in discord's command list
+    #[serde(default)]
+    description: Option<String>,
+
+    /// Endpoint returning a random image
+    url: String,
+
+    /// JSON pointer to the image url in the response, e.g. `/0/url`
+    image_pointer: String,
+
+    /// JSON pointer to a page about the image, if the API gives one
+    #[serde(default)]
+    link_pointer: Option<String>,
+
+    /// Credit shown under each image, e.g. `Images from dog.ceo`
+    #[serde(default)]
+    attribution: Option<String>,
+
+    /// Where to find breeds, for a `breed` option
+    #[serde(default)]
+    breeds: Option<BreedsConfig>,
+
+    /// Image urls to serve while the API is down
+    #[serde(default)]
+    fallback_urls: Vec<String>,
+
+    /// Directory of image files to serve while the API is down
+    #[serde(default)]
+    fallback_dir: Option<String>,
+}
+
+impl ImageProviderConfig {
+    pub fn new(name: &str, url: &str, image_pointer: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            description: None,
+            url: url.to_string(),
+            image_pointer: image_pointer.to_string(),
+            link_pointer: None,
+            attribution: None,
+            breeds: None,
+            fallback_urls: Vec::new(),
+            fallback_dir: None
+        }
+    }
+
+    pub fn with_link_pointer(mut self, link_pointer: &str) -> Self {
+        self.link_pointer = Some(link_pointer.to_string());
+        self
+    }
+
+    pub fn with_attribution(mut self, attribution: &str) -> Self {
+        self.attribution = Some(attribution.to_string());
+        self
+    }
+
+    pub fn with_breeds(mut self, breeds: BreedsConfig) -> Self {
+        self.breeds = Some(breeds);
+        self
+    }
+
+    /// Returns the name of the command
+    pub fn get_name(&self) -> &str { &self.name }
+
+    /// Returns the command's description, or a generated one
+    pub fn get_description(&self) -> String {
+        match &self.description {
+            Some(description) => description.clone(),
+            None => format!("Retrieve a random picture of a {}", self.name)
+        }
+    }
+
+    /// Returns the endpoint returning a random image
+    pub fn get_url(&self) -> &str { &self.url }
+
+    /// Returns the JSON pointer to the image url
+    pub fn get_image_pointer(&self) -> &str { &self.image_pointer }
+
+    /// Returns the JSON pointer to a page about the image, if any
+    pub fn get_link_pointer(&self) -> Option<&str> { self.link_pointer.as_deref() }
+
+    /// Returns the credit shown under each image, if any
+    pub fn get_attribution(&self) -> Option<&str> { self.attribution.as_deref() }
+
+    /// Returns where to find breeds, if the API has them
+    pub fn get_breeds(&self) -> Option<&BreedsConfig> { self.breeds.as_ref() }
+
+    /// Returns the image urls to serve while the API is down
+    pub fn get_fallback_urls(&self) -> &Vec<String> { &self.fallback_urls }
+
+    /// Returns the directory of images to serve while the API is down, if any
+    pub fn get_fallback_dir(&self) -> Option<&str> { self.fallback_dir.as_deref() }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let field = format!("images.{}", self.name);
+
+        let valid_name = (1..=32).contains(&self.name.len())
+            && self.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+        if !valid_name {
+            return Err(invalid("images.name", &format!("`{}` must be 1-32 lowercase letters, digits, `-` or `_`", self.name)));
+        }
+
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(invalid(&format!("{field}.url"), "must be an http or https url"));
+        }
+
+        let pointers = std::iter::once(("image_pointer", Some(&self.image_pointer)))
+            .chain(std::iter::once(("link_pointer", self.link_pointer.as_ref())));
+
+        for (name, pointer) in pointers {
+            if pointer.is_some_and(|pointer| !pointer.is_empty() && !pointer.starts_with('/')) {
+                return Err(invalid(&format!("{field}.{name}"), "must be a JSON pointer starting with `/`"));
+            }
+        }
+
+        if let Some(breeds) = &self.breeds {
+            breeds.validate(&format!("{field}.breeds"))?;
+        }
+
+        if self.fallback_urls.iter().any(|url| !url.starts_with("http://") && !url.starts_with("https://")) {
+            return Err(invalid(&format!("{field}.fallback_urls"), "must all be http or https urls"));
+        }
+
+        Ok(())
+    }
+}
+
+/// How a breed list is laid out
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreedListFormat {
+    /// An array of objects, each with an id and a name
+    List,
+
+    /// An object whose keys are breeds and whose values are arrays of
+    /// sub-breeds, like dog.ceo's. Sub-breeds get ids like `hound/afghan`
+    Map,
+}
+
+/// An `[images.breeds]` table, describing where to
+/// list an API's breeds and fetch images of one
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct BreedsConfig {
+    /// Endpoint returning every breed
+    list_url: String,
+
+    /// How the breed list is laid out
+    #[serde(default = "default_breed_list_format")]
+    format: BreedListFormat,
+
+    /// JSON pointer to the breeds in the response, empty for the whole response
+    #[serde(default)]
+    list_pointer: String,
+
+    /// JSON pointer to each breed's id, for the `list` format
+    #[serde(default = "default_breed_id_pointer")]
+    id_pointer: String,
+
+    /// JSON pointer to each breed's name, for the `list` format
+    #[serde(default = "default_breed_name_pointer")]
+    name_pointer: String,
+
+    /// Endpoint returning a random image of one breed,
+    /// with `{breed}` where the breed's id goes
+    image_url: String,
+
+    /// How long the breed list is kept before fetching it again, in seconds
+    #[serde(default = "default_breed_refresh_secs")]
+    refresh_secs: u64,
+}
+
+fn default_breed_list_format() -> BreedListFormat { BreedListFormat::List }
+
+fn default_breed_id_pointer() -> String { "/id".to_string() }
+
+fn default_breed_name_pointer() -> String { "/name".to_string() }
+
+fn default_breed_refresh_secs() -> u64 { 24 * 60 * 60 }
+
+impl BreedsConfig {
+    pub fn new(list_url: &str, format: BreedListFormat, image_url: &str) -> Self {
+        Self {
+            list_url: list_url.to_string(),
+            format,
+            list_pointer: String::new(),
+            id_pointer: default_breed_id_pointer(),
+            name_pointer: default_breed_name_pointer(),
+            image_url: image_url.to_string(),
+            refresh_secs: default_breed_refresh_secs()
+        }
+    }
+
+    pub fn with_list_pointer(mut self, list_pointer: &str) -> Self {
+        self.list_pointer = list_pointer.to_string();
+        self
+    }
+
+    /// Returns the endpoint returning every breed
+    pub fn get_list_url(&self) -> &str { &self.list_url }
+
+    /// Returns how the breed list is laid out
+    pub fn get_format(&self) -> BreedListFormat { self.format }
+
+    /// Returns the JSON pointer to the breeds in the response
+    pub fn get_list_pointer(&self) -> &str { &self.list_pointer }
+
+    /// Returns the JSON pointer to each breed's id
+    pub fn get_id_pointer(&self) -> &str { &self.id_pointer }
+
+    /// Returns the JSON pointer to each breed's name
+    pub fn get_name_pointer(&self) -> &str { &self.name_pointer }
+
+    /// Returns the image endpoint for the breed with id `breed`
+    pub fn get_image_url(&self, breed: &str) -> String {
+        self.image_url.replace("{breed}", breed)
+    }
+
+    /// Returns how long the breed list is kept
+    pub fn get_refresh_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.refresh_secs)
+    }
+
+    fn validate(&self, field: &str) -> Result<(), ConfigError> {
+        for (name, url) in [("list_url", &self.list_url), ("image_url", &self.image_url)] {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(invalid(&format!("{field}.{name}"), "must be an http or https url"));
+            }
+        }
+
+        if !self.image_url.contains("{breed}") {
+            return Err(invalid(&format!("{field}.image_url"), "must contain `{breed}`"));
+        }
+
+        let pointers = [
+            ("list_pointer", &self.list_pointer),
+            ("id_pointer", &self.id_pointer),
+            ("name_pointer", &self.name_pointer)
+        ];
+
+        for (name, pointer) in pointers {
+            if !pointer.is_empty() && !pointer.starts_with('/') {
+                return Err(invalid(&format!("{field}.{name}"), "must be a JSON pointer starting with `/`"));
+            }
+        }
+
+        if self.refresh_secs == 0 {
+            return Err(invalid(&format!("{field}.refresh_secs"), "must be more than 0"));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_images() -> Vec<ImageProviderConfig> {
+    vec![
+        ImageProviderConfig::new("cat", "https://api.thecatapi.com/v1/images/search", "/0/url")
+            .with_attribution("Images from thecatapi.com")
+            .with_breeds(BreedsConfig::new(
+                "https://api.thecatapi.com/v1/breeds",
+                BreedListFormat::List,
+                "https://api.thecatapi.com/v1/images/search?breed_ids={breed}"
+            )),
+        ImageProviderConfig::new("dog", "https://dog.ceo/api/breeds/image/random", "/message")
+            .with_attribution("Images from dog.ceo")
+            .with_breeds(BreedsConfig::new(
+                "https://dog.ceo/api/breeds/list/all",
+                BreedListFormat::Map,
+                "https://dog.ceo/api/breed/{breed}/images/random"
+            ).with_list_pointer("/message")),
+        ImageProviderConfig::new("fox", "https://randomfox.ca/floof/", "/image")
+            .with_link_pointer("/link")
+            .with_attribution("Images from randomfox.ca"),
+        ImageProviderConfig::new("duck", "https://random-d.uk/api/v2/random", "/url")
+            .with_attribution("Images from random-d.uk"),
+        ImageProviderConfig::new("bunny", "https://api.bunnies.io/v2/loop/random/?media=gif,png", "/media/gif")
+            .with_attribution("Images from bunnies.io")
+    ]
+}
+
+fn restart_warning(field: &str) -> String {
+    format!("`{field}` changed, but only takes effect after a restart")
+}
+
+fn invalid(field: &str, reason: &str) -> ConfigError {
+    ConfigError::InvalidValue(field.to_string(), reason.to_string())
 }
 
 pub struct ConfigData;
@@ -39,22 +856,100 @@ impl TypeMapKey for ConfigData {
 }
 
 impl Config {
-    /// Attempts to read a file into a string, then parse it
-    /// using toml.
+    /// Reads the config file at `path`, applies any `RITTOU_*`
+    /// environment variables over it, reads `token_file` and
+    /// `secret_key_file`, then validates the result.
     /// 
-    /// Returns a `ConfigError` if either step fails.
+    /// Returns a `ConfigError` if any step fails.
     pub fn from_file(path: &str) -> Result<Self, ConfigError> {
-        if let Ok(contents) = std::fs::read_to_string(path) {
-            if let Ok(config) = toml::from_str::<Self>(contents.as_str()) {
-                Ok(config)
-            } else {
-                Err(ConfigError::ConfigParseError)
-            }
-        } else {
-            Err(ConfigError::ConfigReadError)
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => return Err(ConfigError::ConfigReadError(path.to_string(), e))
+        };
+
+        // parse the file on its own first, so mistakes
+        // in it are reported with their line and column
+        if let Err(e) = toml::from_str::<Self>(contents.as_str()) {
+            return Err(ConfigError::ConfigParseError(path.to_string(), e));
+        }
+
+        let table = match contents.parse::<Table>() {
+            Ok(table) => table,
+            Err(e) => return Err(ConfigError::ConfigParseError(path.to_string(), e))
+        };
+
+        let mut config = Self::from_table(table, path)?;
+        config.path = Some(path.to_string());
+
+        Ok(config)
+    }
+
+    /// Builds a config from `RITTOU_*` environment variables alone,
+    /// for deployments without a config file
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_table(Table::new(), "environment")
+    }
+
+    /// Reads the config at `path` if one is given. Otherwise reads
+    /// `DEFAULT_CONFIG_PATH`, falling back to environment variables
+    /// alone if that file doesn't exist.
+    pub fn load(path: Option<&str>) -> Result<Self, ConfigError> {
+        match path {
+            Some(path) => Self::from_file(path),
+            None if std::path::Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(DEFAULT_CONFIG_PATH),
+            None => Self::from_env()
+        }
+    }
+
+    fn from_table(mut table: Table, source: &str) -> Result<Self, ConfigError> {
+        apply_env_overrides(&mut table, std::env::vars())?;
+
+        let mut config = match Value::Table(table).try_into::<Self>() {
+            Ok(config) => config,
+            Err(e) => return Err(ConfigError::ConfigParseError(source.to_string(), e))
+        };
+
+        if let Some(token) = read_secret_file("token", &config.token, &config.token_file)? {
+            config.token = token;
+        }
+
+        if let Some(secret_key) = read_secret_file("secret_key", &config.secret_key, &config.secret_key_file)? {
+            config.secret_key = secret_key;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Reads the config again from where it was first loaded.
+    /// 
+    /// Values that only take effect on startup, like the token, keep
+    /// their current value, and a warning is returned for each one
+    /// that changed.
+    pub fn reload(&self) -> Result<(Self, Vec<String>), ConfigError> {
+        let mut config = Self::load(self.path.as_deref())?;
+        let mut warnings = Vec::new();
+
+        if config.token != self.token {
+            warnings.push(restart_warning("token"));
+            config.token = self.token.clone();
         }
+
+        if config.secret_key != self.secret_key || config.retired_secret_keys != self.retired_secret_keys {
+            warnings.push(restart_warning("secret_key"));
+            config.secret_key = self.secret_key.clone();
+            config.retired_secret_keys = self.retired_secret_keys.clone();
+        }
+
+        warnings.extend(config.cache.keep_restart_only(&self.cache));
+
+        Ok((config, warnings))
     }
 
+    /// Returns the file the config was read from, if any
+    pub fn get_path(&self) -> Option<&str> { self.path.as_deref() }
+
     /// Returns the value of `token`
     pub fn get_token(&self) -> &String { &self.token }
 
@@ -67,4 +962,311 @@ impl Config {
     }
 
     pub fn get_secret_key(&self) -> &String { &self.secret_key }
-}
\ No newline at end of file
+
+    /// Returns secret keys that are no longer used to encrypt,
+    /// but may still be needed to decrypt
+    pub fn get_retired_secret_keys(&self) -> &Vec<String> { &self.retired_secret_keys }
+
+    /// Returns the `[cache]` section
+    pub fn get_cache_config(&self) -> &CacheConfig { &self.cache }
+
+    /// Returns the `[scramblr]` section
+    pub fn get_scramblr_config(&self) -> &ScramblrConfig { &self.scramblr }
+
+    /// Returns the `[commands]` section
+    pub fn get_commands_config(&self) -> &CommandsConfig { &self.commands }
+
+    /// Returns the `[http]` section
+    pub fn get_http_config(&self) -> &HttpConfig { &self.http }
+
+    /// Returns the `[fallback]` section
+    pub fn get_fallback_config(&self) -> &FallbackConfig { &self.fallback }
+
+    /// Returns every `[[images]]` table
+    pub fn get_image_providers(&self) -> &Vec<ImageProviderConfig> { &self.images }
+
+    /// Checks every value is within its allowed range
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.token.trim().is_empty() {
+            return Err(invalid("token", "must be set, directly or with `token_file`"));
+        }
+
+        if self.secret_key.len() < MIN_SECRET_KEY_LEN {
+            return Err(invalid("secret_key", &format!("must be at least {MIN_SECRET_KEY_LEN} characters long")));
+        }
+
+        self.cache.validate()?;
+        self.commands.validate()?;
+        self.http.validate()?;
+        self.fallback.validate()?;
+
+        for (i, image) in self.images.iter().enumerate() {
+            image.validate()?;
+
+            if self.images[..i].iter().any(|other| other.name == image.name) {
+                return Err(invalid("images.name", &format!("`{}` is used more than once", image.name)));
+            }
+        }
+
+        self.scramblr.validate(&self.cache)
+    }
+}
+
+/// Sets a value in `table` for each `RITTOU_*` variable in `vars`.
+/// 
+/// Values are read as TOML where possible, so lists and numbers can
+/// be given as `["!", "?"]` or `300`. Anything else, any value
+/// replacing a string, or any field in `ENV_STRING_FIELDS` is used
+/// as a plain string. A bare value for a field in `ENV_LIST_FIELDS`,
+/// or one replacing a list, is used as a list of just that value.
+fn apply_env_overrides(table: &mut Table, vars: impl Iterator<Item = (String, String)>) -> Result<(), ConfigError> {
+    for (name, raw_value) in vars {
+        let path = match name.strip_prefix(ENV_PREFIX) {
+            Some(path) if !path.is_empty() => path.to_lowercase(),
+            _ => continue
+        };
+
+        let keys = path.split("__").collect::<Vec<&str>>();
+
+        if keys.iter().any(|key| key.is_empty()) {
+            return Err(ConfigError::EnvVarError(name, "empty section or field name".to_string()));
+        }
+
+        let (field, sections) = match keys.split_last() {
+            Some(split) => split,
+            None => continue
+        };
+
+        // the path without list indices, as the field lists give it
+        let field_path = keys.iter()
+            .filter(|key| key.parse::<usize>().is_err())
+            .copied()
+            .collect::<Vec<&str>>()
+            .join("__");
+
+        let mut current = &mut *table;
+        let mut sections = sections.iter().peekable();
+
+        while let Some(section) = sections.next() {
+            let index = sections.peek().and_then(|key| key.parse::<usize>().ok());
+
+            let entry = match index {
+                Some(index) => {
+                    sections.next();
+
+                    match current.get_mut(*section) {
+                        Some(Value::Array(entries)) => match entries.get_mut(index) {
+                            Some(entry) => entry,
+                            None => return Err(ConfigError::EnvVarError(name, format!("`{section}` has no entry {index} in the config file")))
+                        },
+                        _ => return Err(ConfigError::EnvVarError(name, format!("`{section}` is not a list in the config file")))
+                    }
+                },
+                None => current
+                    .entry(section.to_string())
+                    .or_insert_with(|| Value::Table(Table::new()))
+            };
+
+            current = match entry {
+                Value::Table(section) => section,
+                Value::Array(_) => return Err(ConfigError::EnvVarError(
+                    name,
+                    format!("`{section}` is a list, so pick an entry by its index, e.g. `{ENV_PREFIX}{}__0__...`", section.to_uppercase())
+                )),
+                _ => return Err(ConfigError::EnvVarError(name, format!("`{section}` is not a section")))
+            };
+        }
+
+        let is_string = ENV_STRING_FIELDS.contains(&field_path.as_str())
+            || matches!(current.get(*field), Some(Value::String(_)));
+
+        let is_list = ENV_LIST_FIELDS.contains(&field_path.as_str())
+            || matches!(current.get(*field), Some(Value::Array(_)));
+
+        let parsed = match format!("value = {raw_value}").parse::<Table>() {
+            Ok(mut parsed) if !is_string => parsed.remove("value"),
+            _ => None
+        };
+
+        let value = match parsed {
+            Some(Value::Array(values)) => Value::Array(values),
+            Some(_) | None if is_list => Value::Array(vec![Value::String(raw_value)]),
+            Some(value) => value,
+            None => Value::String(raw_value)
+        };
+
+        current.insert(field.to_string(), value);
+    }
+
+    Ok(())
+}
+
+/// Reads the value of `field` from `file`, if one is set. Setting
+/// both the value and its file is rejected as ambiguous.
+fn read_secret_file(field: &str, value: &str, file: &Option<String>) -> Result<Option<String>, ConfigError> {
+    let file = match file {
+        Some(file) => file,
+        None => return Ok(None)
+    };
+
+    if !value.is_empty() {
+        return Err(invalid(field, &format!("set either `{field}` or `{field}_file`, not both")));
+    }
+
+    match std::fs::read_to_string(file) {
+        Ok(contents) => Ok(Some(contents.trim().to_string())),
+        Err(e) => Err(ConfigError::SecretFileError(field.to_string(), file.clone(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(table: &mut Table, vars: &[(&str, &str)]) -> Result<(), ConfigError> {
+        let vars = vars.iter().map(|(name, value)| (name.to_string(), value.to_string()));
+
+        apply_env_overrides(table, vars)
+    }
+
+    fn overridden(vars: &[(&str, &str)]) -> Table {
+        let mut table = Table::new();
+        apply(&mut table, vars).unwrap();
+
+        table
+    }
+
+    #[test]
+    fn string_fields_stay_strings() {
+        let table = overridden(&[
+            ("RITTOU_TOKEN", "123"),
+            ("RITTOU_SECRET_KEY", "true"),
+            ("RITTOU_CACHE__PATH", "2024"),
+            ("RITTOU_CACHE__TOML_PATH", "false")
+        ]);
+
+        assert_eq!(table["token"], Value::String("123".to_string()));
+        assert_eq!(table["secret_key"], Value::String("true".to_string()));
+        assert_eq!(table["cache"]["path"], Value::String("2024".to_string()));
+        assert_eq!(table["cache"]["toml_path"], Value::String("false".to_string()));
+    }
+
+    #[test]
+    fn numbers_bools_and_lists_are_parsed() {
+        let table = overridden(&[
+            ("RITTOU_CACHE__MAX_USER_MESSAGES", "300"),
+            ("RITTOU_COMMANDS__GLOBAL", "true"),
+            ("RITTOU_PREFIXES", r#"["!", "?"]"#)
+        ]);
+
+        assert_eq!(table["cache"]["max_user_messages"], Value::Integer(300));
+        assert_eq!(table["commands"]["global"], Value::Boolean(true));
+        assert_eq!(table["prefixes"], Value::Array(vec![Value::String("!".to_string()), Value::String("?".to_string())]));
+    }
+
+    #[test]
+    fn unparsable_values_are_strings() {
+        let table = overridden(&[("RITTOU_CACHE__BACKEND", "polodb"), ("RITTOU_NOTE", "not toml")]);
+
+        assert_eq!(table["cache"]["backend"], Value::String("polodb".to_string()));
+        assert_eq!(table["note"], Value::String("not toml".to_string()));
+    }
+
+    #[test]
+    fn values_replacing_strings_stay_strings() {
+        let mut table = "[extra]\nname = \"cat\"".parse::<Table>().unwrap();
+        apply(&mut table, &[("RITTOU_EXTRA__NAME", "42")]).unwrap();
+
+        assert_eq!(table["extra"]["name"], Value::String("42".to_string()));
+    }
+
+    #[test]
+    fn other_variables_are_ignored() {
+        let table = overridden(&[("HOME", "/root"), ("RITTOU_", "1"), ("RITTOUTOKEN", "1")]);
+
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn empty_names_are_rejected() {
+        let result = apply(&mut Table::new(), &[("RITTOU_CACHE____PATH", "cache.db")]);
+
+        assert!(matches!(result, Err(ConfigError::EnvVarError(..))));
+    }
+
+    #[test]
+    fn fields_are_not_sections() {
+        let mut table = "token = \"abc\"".parse::<Table>().unwrap();
+        let result = apply(&mut table, &[("RITTOU_TOKEN__PATH", "x")]);
+
+        assert!(matches!(result, Err(ConfigError::EnvVarError(..))));
+    }
+
+    #[test]
+    fn numeric_token_deserializes() {
+        let table = overridden(&[("RITTOU_TOKEN", "123"), ("RITTOU_SECRET_KEY", "true")]);
+        let config = Value::Table(table).try_into::<Config>().unwrap();
+
+        assert_eq!(config.get_token(), "123");
+        assert_eq!(config.get_secret_key(), "true");
+    }
+
+    #[test]
+    fn bare_values_become_lists() {
+        let table = overridden(&[("RITTOU_PREFIXES", "!"), ("RITTOU_RETIRED_SECRET_KEYS", "123")]);
+
+        assert_eq!(table["prefixes"], Value::Array(vec![Value::String("!".to_string())]));
+        assert_eq!(table["retired_secret_keys"], Value::Array(vec![Value::String("123".to_string())]));
+
+        let config = Value::Table(table).try_into::<Config>().unwrap();
+
+        assert_eq!(config.get_prefixes(), &vec!["!".to_string()]);
+    }
+
+    const IMAGES: &str = r#"
+        [[images]]
+        name = "cat"
+        url = "https://cat.example/random"
+
+        [[images]]
+        name = "dog"
+        url = "https://dog.example/random"
+    "#;
+
+    #[test]
+    fn list_entries_are_picked_by_index() {
+        let mut table = IMAGES.parse::<Table>().unwrap();
+
+        apply(&mut table, &[
+            ("RITTOU_IMAGES__1__URL", "https://dog.example/other"),
+            ("RITTOU_IMAGES__0__FALLBACK_URLS", "https://cat.example/1.png"),
+            ("RITTOU_IMAGES__1__BREEDS__REFRESH_SECS", "60")
+        ]).unwrap();
+
+        assert_eq!(table["images"][0]["url"], Value::String("https://cat.example/random".to_string()));
+        assert_eq!(table["images"][1]["url"], Value::String("https://dog.example/other".to_string()));
+        assert_eq!(table["images"][0]["fallback_urls"], Value::Array(vec![Value::String("https://cat.example/1.png".to_string())]));
+        assert_eq!(table["images"][1]["breeds"]["refresh_secs"], Value::Integer(60));
+    }
+
+    #[test]
+    fn missing_list_entries_are_rejected() {
+        let mut table = IMAGES.parse::<Table>().unwrap();
+
+        let past_end = apply(&mut table, &[("RITTOU_IMAGES__2__URL", "https://x.example")]);
+        let no_list = apply(&mut Table::new(), &[("RITTOU_IMAGES__0__URL", "https://x.example")]);
+
+        assert!(matches!(past_end, Err(ConfigError::EnvVarError(..))));
+        assert!(matches!(no_list, Err(ConfigError::EnvVarError(..))));
+    }
+
+    #[test]
+    fn lists_need_an_index() {
+        let mut table = IMAGES.parse::<Table>().unwrap();
+
+        match apply(&mut table, &[("RITTOU_IMAGES__URL", "https://x.example")]) {
+            Err(ConfigError::EnvVarError(_, reason)) => assert!(reason.contains("RITTOU_IMAGES__0__")),
+            _ => panic!("Expected an EnvVarError")
+        }
+    }
+}
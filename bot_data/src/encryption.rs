@@ -1,13 +1,37 @@
-use aes_gcm::{aead::generic_array::GenericArray, aes::Aes256};
+use std::collections::HashMap;
+
+use aes_gcm::aead::generic_array::GenericArray;
 use aes_gcm_siv::{
     aead::{Aead, KeyInit},
     Aes256GcmSiv, Nonce // Or `Aes128GcmSiv`
 };
-use rand::{Rng, distributions::Alphanumeric};
+use hkdf::Hkdf;
+use rand::Rng;
+use sha2::Sha256;
 use md5::compute;
 
 use crate::config::Config;
 
+/// Id of the key messages were encrypted with before keys were
+/// derived with HKDF; the MD5 digest of a secret key
+pub const LEGACY_KEY_ID: u32 = 0;
+
+/// Default location of the keyring file
+pub const DEFAULT_KEYRING_PATH: &str = "data/keyring.toml";
+
+/// Length of a key salt, in bytes
+const SALT_LEN: usize = 16;
+
+/// Length of a nonce, in bytes (96-bits)
+const NONCE_LEN: usize = 12;
+
+/// HKDF info for message encryption keys
+const KEY_INFO: &[u8] = b"rittou message cache key";
+
+/// HKDF info for the value used to recognise which
+/// secret a stored key was derived from
+const CHECK_INFO: &[u8] = b"rittou message cache key check";
+
 #[derive(Debug, thiserror::Error)]
 pub enum CryptionError {
     #[error("Failed to encrypt string: {0}")]
@@ -17,58 +41,360 @@ pub enum CryptionError {
     #[error("Could not find nonce")]
     MissingNonce,
     #[error("Missing ciphered text (this shouldn't appear!)")]
-    MissingCipher
+    MissingCipher,
+    #[error("No secret key is known for key id {0}")]
+    UnknownKey(u32),
+    #[error("Failed to derive key: {0}")]
+    KeyDerivationFailed(String),
+    #[error("Failed to read keyring: {0}")]
+    KeyringReadFailed(String),
+    #[error("Failed to write keyring: {0}")]
+    KeyringWriteFailed(String),
+    #[error("No data key is stored for user {0}")]
+    MissingDataKey(u64)
+}
+
+/// Text encrypted by a `Keyring`
+pub struct EncryptedText {
+    /// Id of the key the text was encrypted with
+    pub key_id: u32,
+    pub data: Vec<u8>,
+    pub nonce: Vec<u8>
+}
+
+/// A stored key derivation. The key itself is never written,
+/// only the salt it was derived with and a check value to
+/// match it back up with its secret
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct KeyRecord {
+    id: u32,
+    salt: Vec<u8>,
+    check: Vec<u8>
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct KeyringFile {
+    keys: Vec<KeyRecord>
+}
+
+/// Every key the message cache can encrypt or decrypt with.
+///
+/// The current `secret_key` derives the key new messages are
+/// encrypted with, while `retired_secret_keys` keep messages
+/// from before a key rotation readable until they are
+/// re-encrypted.
+pub struct Keyring {
+    path: Option<String>,
+    records: Vec<KeyRecord>,
+    ciphers: HashMap<u32, Aes256GcmSiv>,
+    legacy_ciphers: Vec<Aes256GcmSiv>,
+    current_key_id: u32
 }
 
-pub fn encrypt(text: &str, config: &Config) -> Result<(Vec<u8>, String), CryptionError> {
-    let nonce_str = gen_nonce();
+impl Keyring {
+    /// Opens the keyring at `DEFAULT_KEYRING_PATH` using
+    /// the secret keys in `config`
+    pub fn from_config(config: &Config) -> Result<Self, CryptionError> {
+        Self::open(Some(DEFAULT_KEYRING_PATH), config.get_secret_key(), config.get_retired_secret_keys())
+    }
+
+    /// Opens the keyring file at `path`, matching its keys up with
+    /// `secret_key` and `retired_secret_keys`. A key derivation is
+    /// added and saved if `secret_key` has never been seen before.
+    ///
+    /// Without a `path`, nothing is read or written.
+    pub fn open(path: Option<&str>, secret_key: &str, retired_secret_keys: &[String]) -> Result<Self, CryptionError> {
+        let file = match path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => match toml::from_str::<KeyringFile>(contents.as_str()) {
+                    Ok(file) => file,
+                    Err(e) => return Err(CryptionError::KeyringReadFailed(e.to_string()))
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => KeyringFile::default(),
+                Err(e) => return Err(CryptionError::KeyringReadFailed(e.to_string()))
+            },
+            None => KeyringFile::default()
+        };
+
+        let mut keyring = Self {
+            path: path.map(str::to_string),
+            records: file.keys,
+            ciphers: HashMap::new(),
+            legacy_ciphers: Vec::new(),
+            current_key_id: LEGACY_KEY_ID
+        };
+
+        let secrets = std::iter::once(secret_key)
+            .chain(retired_secret_keys.iter().map(String::as_str));
+
+        for secret in secrets {
+            keyring.legacy_ciphers.push(get_legacy_cipher(secret));
+
+            for record in &keyring.records {
+                if keyring.ciphers.contains_key(&record.id) {
+                    continue;
+                }
+
+                if derive(secret, &record.salt, CHECK_INFO)? == record.check {
+                    keyring.ciphers.insert(record.id, Aes256GcmSiv::new(GenericArray::from_slice(&derive(secret, &record.salt, KEY_INFO)?)));
+                }
+            }
+        }
+
+        let current = keyring.records.iter()
+            .find(|record| derive(secret_key, &record.salt, CHECK_INFO).ok().as_ref() == Some(&record.check))
+            .map(|record| record.id);
+
+        keyring.current_key_id = match current {
+            Some(id) => id,
+            None => keyring.add_key(secret_key)?
+        };
+
+        Ok(keyring)
+    }
+
+    /// Returns the id of the key new messages are encrypted with
+    pub fn get_current_key_id(&self) -> u32 { self.current_key_id }
+
+    /// Encrypts `text` with the current key
+    pub fn encrypt(&self, text: &str) -> Result<EncryptedText, CryptionError> {
+        self.encrypt_bytes(text.as_bytes())
+    }
+
+    /// Decrypts `data` with the key it was encrypted with
+    pub fn decrypt(&self, key_id: u32, data: &[u8], nonce: &[u8]) -> Result<String, CryptionError> {
+        let plaintext = self.decrypt_bytes(key_id, data, nonce)?;
+
+        Ok(String::from_utf8_lossy(&plaintext).to_string())
+    }
+
+    /// Encrypts raw `bytes` with the current key
+    pub fn encrypt_bytes(&self, bytes: &[u8]) -> Result<EncryptedText, CryptionError> {
+        let cipher = match self.ciphers.get(&self.current_key_id) {
+            Some(cipher) => cipher,
+            None => return Err(CryptionError::UnknownKey(self.current_key_id))
+        };
+
+        let (data, nonce) = encrypt_with(cipher, bytes)?;
+
+        Ok(EncryptedText {
+            key_id: self.current_key_id,
+            data,
+            nonce
+        })
+    }
+
+    /// Decrypts raw bytes with the key they were encrypted with
+    pub fn decrypt_bytes(&self, key_id: u32, data: &[u8], nonce: &[u8]) -> Result<Vec<u8>, CryptionError> {
+        if key_id == LEGACY_KEY_ID {
+            // legacy keys aren't tagged with the secret they came
+            // from, so try each known secret in turn
+            let mut plaintext = Err(CryptionError::UnknownKey(key_id));
+
+            for cipher in &self.legacy_ciphers {
+                plaintext = decrypt_with(cipher, data, nonce);
+
+                if plaintext.is_ok() {
+                    break;
+                }
+            }
+
+            plaintext
+        } else {
+            match self.ciphers.get(&key_id) {
+                Some(cipher) => decrypt_with(cipher, data, nonce),
+                None => Err(CryptionError::UnknownKey(key_id))
+            }
+        }
+    }
+
+    /// Derives a key for `secret_key` under a fresh salt,
+    /// saves it and returns its id
+    fn add_key(&mut self, secret_key: &str) -> Result<u32, CryptionError> {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill(&mut salt);
+
+        let id = self.records.iter()
+            .map(|record| record.id)
+            .max()
+            .unwrap_or(LEGACY_KEY_ID) + 1;
+
+        let key = derive(secret_key, &salt, KEY_INFO)?;
+
+        self.records.push(KeyRecord {
+            id,
+            salt: salt.to_vec(),
+            check: derive(secret_key, &salt, CHECK_INFO)?
+        });
 
-    let cipher = get_key_cipher(config.get_secret_key().clone());
+        self.ciphers.insert(id, Aes256GcmSiv::new(GenericArray::from_slice(&key)));
+
+        self.save()?;
+
+        Ok(id)
+    }
+
+    fn save(&self) -> Result<(), CryptionError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(())
+        };
+
+        let file = KeyringFile { keys: self.records.clone() };
+
+        let contents = match toml::to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) => return Err(CryptionError::KeyringWriteFailed(e.to_string()))
+        };
+
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                return Err(CryptionError::KeyringWriteFailed(e.to_string()));
+            }
+        }
+
+        match std::fs::write(path, contents) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(CryptionError::KeyringWriteFailed(e.to_string()))
+        }
+    }
+}
+
+/// Encrypts `bytes` with `cipher` under a fresh nonce,
+/// returning the ciphertext and the nonce
+pub fn encrypt_with(cipher: &Aes256GcmSiv, bytes: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptionError> {
+    let nonce_bytes = gen_nonce();
 
     // 96-bits; unique per message
-    let nonce = Nonce::from_slice(nonce_str.as_bytes());
-    let ciphertext = match cipher.encrypt(&nonce, text.as_bytes()) {
+    let nonce = Nonce::from_slice(&nonce_bytes);
+    let ciphertext = match cipher.encrypt(nonce, bytes) {
         Ok(cipher) => cipher,
         Err(e) => return Err(CryptionError::EncryptFailed(e.to_string()))
     };
 
-    let result = (ciphertext, nonce_str);
+    Ok((ciphertext, nonce_bytes.to_vec()))
+}
+
+/// Decrypts `data` with `cipher`
+pub fn decrypt_with(cipher: &Aes256GcmSiv, data: &[u8], nonce: &[u8]) -> Result<Vec<u8>, CryptionError> {
+    if nonce.len() != NONCE_LEN {
+        return Err(CryptionError::MissingNonce);
+    }
+
+    // 96-bits; unique per message
+    let nonce = Nonce::from_slice(nonce);
 
-    Ok(result)
+    match cipher.decrypt(nonce, data) {
+        Ok(plaintext) => Ok(plaintext),
+        Err(e) => Err(CryptionError::DecryptFailed(e.to_string()))
+    }
 }
 
-pub fn decrypt(input: (&Vec<u8>, &String), config: &Config) -> Result<String, CryptionError> {
-    let ciphertext = input.0;
-    let nonce_str = input.1;
+/// Generates a random 256-bit data key and its cipher
+pub fn gen_data_key() -> (Vec<u8>, Aes256GcmSiv) {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill(&mut key);
 
-    let cipher = get_key_cipher(config.get_secret_key().clone());
+    let cipher = Aes256GcmSiv::new(GenericArray::from_slice(&key));
 
-    // 96-bits; unique per message
-    let nonce = Nonce::from_slice(nonce_str.as_bytes());
-    
-    let plaintext = match cipher.decrypt(&nonce, ciphertext.as_ref()) {
-        Ok(cipher) => cipher,
-        Err(e) => return Err(CryptionError::DecryptFailed(e.to_string()))
-    };
+    (key.to_vec(), cipher)
+}
 
-    let result = String::from_utf8_lossy(&plaintext).to_string();
+/// Creates a cipher from the raw bytes of a data key
+pub fn data_key_cipher(key: &[u8]) -> Result<Aes256GcmSiv, CryptionError> {
+    match Aes256GcmSiv::new_from_slice(key) {
+        Ok(cipher) => Ok(cipher),
+        Err(e) => Err(CryptionError::KeyDerivationFailed(e.to_string()))
+    }
+}
 
-    Ok(result)
+fn gen_nonce() -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill(&mut nonce);
+
+    nonce
 }
 
-fn gen_nonce() -> String {
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(12)
-        .map(char::from)
-        .collect::<String>()
+/// Derives 32 bytes from `secret_key` and `salt` with HKDF-SHA256
+fn derive(secret_key: &str, salt: &[u8], info: &[u8]) -> Result<Vec<u8>, CryptionError> {
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), secret_key.as_bytes());
+
+    let mut okm = [0u8; 32];
+
+    match hkdf.expand(info, &mut okm) {
+        Ok(_) => Ok(okm.to_vec()),
+        Err(e) => Err(CryptionError::KeyDerivationFailed(e.to_string()))
+    }
 }
 
-fn get_key_cipher(key: String) -> Aes256GcmSiv {
+/// The key messages were encrypted with before the keyring
+/// existed: the 32 ASCII bytes of the secret's MD5 hex digest
+fn get_legacy_cipher(key: &str) -> Aes256GcmSiv {
     let digest = compute(key);
     let digested_secret = format!("{:x}", digest);
 
     let key = GenericArray::from_slice(digested_secret.as_bytes());
-    
-    Aes256GcmSiv::new(&key)
-}
\ No newline at end of file
+
+    Aes256GcmSiv::new(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::keyring_path;
+
+    #[test]
+    fn round_trip() {
+        let keyring = Keyring::open(None, "secret", &[]).expect("Expected keyring");
+        let encrypted = keyring.encrypt("hello there").expect("Expected encryption");
+
+        assert_ne!(encrypted.key_id, LEGACY_KEY_ID);
+        assert_eq!(keyring.decrypt(encrypted.key_id, &encrypted.data, &encrypted.nonce).unwrap(), "hello there");
+    }
+
+    #[test]
+    fn legacy_key_still_decrypts() {
+        let (data, nonce) = encrypt_with(&get_legacy_cipher("secret"), b"old message").unwrap();
+
+        let keyring = Keyring::open(None, "secret", &[]).expect("Expected keyring");
+        assert_eq!(keyring.decrypt(LEGACY_KEY_ID, &data, &nonce).unwrap(), "old message");
+
+        // and after the secret is retired
+        let keyring = Keyring::open(None, "new secret", &["secret".to_string()]).expect("Expected keyring");
+        assert_eq!(keyring.decrypt(LEGACY_KEY_ID, &data, &nonce).unwrap(), "old message");
+
+        let keyring = Keyring::open(None, "unrelated", &[]).expect("Expected keyring");
+        assert!(keyring.decrypt(LEGACY_KEY_ID, &data, &nonce).is_err());
+    }
+
+    #[test]
+    fn rotation_adds_a_new_current_key() {
+        let path = keyring_path("rotation");
+
+        let old = Keyring::open(Some(&path), "old secret", &[]).expect("Expected keyring");
+        let encrypted = old.encrypt("before rotation").unwrap();
+
+        let rotated = Keyring::open(Some(&path), "new secret", &["old secret".to_string()]).expect("Expected keyring");
+        assert_ne!(rotated.get_current_key_id(), old.get_current_key_id());
+        assert_eq!(rotated.decrypt(encrypted.key_id, &encrypted.data, &encrypted.nonce).unwrap(), "before rotation");
+
+        let reencrypted = rotated.encrypt("before rotation").unwrap();
+        assert_eq!(reencrypted.key_id, rotated.get_current_key_id());
+
+        // once the old secret is dropped, only the new key is readable
+        let dropped = Keyring::open(Some(&path), "new secret", &[]).expect("Expected keyring");
+        assert_eq!(dropped.get_current_key_id(), rotated.get_current_key_id());
+        assert!(matches!(dropped.decrypt(encrypted.key_id, &encrypted.data, &encrypted.nonce), Err(CryptionError::UnknownKey(_))));
+        assert_eq!(dropped.decrypt(reencrypted.key_id, &reencrypted.data, &reencrypted.nonce).unwrap(), "before rotation");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn wrong_nonce_length_is_rejected() {
+        let keyring = Keyring::open(None, "secret", &[]).expect("Expected keyring");
+        let encrypted = keyring.encrypt("hello").unwrap();
+
+        assert!(matches!(keyring.decrypt(encrypted.key_id, &encrypted.data, &[0; 4]), Err(CryptionError::MissingNonce)));
+    }
+}
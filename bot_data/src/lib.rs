@@ -1,4 +1,9 @@
 pub mod encryption;
+pub mod user_keys;
 pub mod user_message_cache;
 pub mod scramblr;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod settings;
+
+#[cfg(test)]
+pub(crate) mod test_util;
@@ -1,39 +1,62 @@
 use rand::{seq::SliceRandom, thread_rng, Rng};
 use serenity::model::user::User;
 
-use crate::{user_message_cache::UserMessageCache, encryption::decrypt, config::Config};
+use crate::{user_message_cache::{UserMessageCache, CacheMessage}, settings::Settings, config::ScramblrConfig};
 
 #[derive(thiserror::Error, Debug)]
 pub enum ScramblrError {
     #[error("One or more users is a bot")]
     IsBot,
-    #[error("{0} has too few messages. Minimum cached message count is `3`")]
-    TooFewMessages(String),
+    #[error("{0} has too few messages. Minimum cached message count is `{1}`")]
+    TooFewMessages(String, usize),
     #[error("No message matches were found")]
     NoMatches,
     #[error("Failed to decrypt a message")]
-    DecryptionError
+    DecryptionError,
+    #[error("Failed to read the message cache: {0}")]
+    CacheError(String),
+    #[error("{0} has opted out of message caching")]
+    OptedOut(String),
+    #[error("Messages can only be scrambled in a server")]
+    NotInGuild
 }
 
 pub fn get_scrambled_message(
     user_a: &User,
     user_b: &User,
+    guild_id: Option<u64>,
     user_message_cache: &UserMessageCache,
-    config: &Config
+    settings: &Settings,
+    scramblr_config: &ScramblrConfig
 ) -> Result<String, ScramblrError> {
     if user_a.bot || user_b.bot {
         return Err(ScramblrError::IsBot);
     }
 
-    let user_a_messages = user_message_cache.get_user_messages(user_a.id.get()).unwrap_or(Vec::new());
-    let user_b_messages = user_message_cache.get_user_messages(user_b.id.get()).unwrap_or(Vec::new());
+    for user in [user_a, user_b] {
+        if !settings.is_caching_enabled(user.id.get()) {
+            return Err(ScramblrError::OptedOut(user.tag()));
+        }
+    }
+
+    let guild_id = match guild_id {
+        Some(guild_id) => guild_id,
+        None => return Err(ScramblrError::NotInGuild)
+    };
 
-    if user_a_messages.len() <= 3 {
-        return Err(ScramblrError::TooFewMessages(user_a.tag()))
+    let user_a_messages = get_usable_messages(user_a, guild_id, user_message_cache, settings)?;
+    let user_b_messages = get_usable_messages(user_b, guild_id, user_message_cache, settings)?;
+
+    let limits = settings.get_guild_limits(guild_id);
+    let min_messages = limits.scramblr_min_messages.unwrap_or(scramblr_config.get_min_messages());
+    let max_tries = limits.scramblr_max_tries.unwrap_or(scramblr_config.get_max_tries());
+
+    if user_a_messages.len() < min_messages {
+        return Err(ScramblrError::TooFewMessages(user_a.tag(), min_messages))
     }
 
-    if user_b_messages.len() <= 3 {
-        return Err(ScramblrError::TooFewMessages(user_b.tag()))
+    if user_b_messages.len() < min_messages {
+        return Err(ScramblrError::TooFewMessages(user_b.tag(), min_messages))
     }
 
     let mut scramble_tries = 0;
@@ -41,7 +64,7 @@ pub fn get_scrambled_message(
 
     let mut last_msg = None;
 
-    while scramble_tries < 25 {
+    while scramble_tries < max_tries {
         // choose a random message
         let user_a_msg = user_a_messages.choose(&mut rng);
         let user_b_msg = user_b_messages.choose(&mut rng);
@@ -50,18 +73,20 @@ pub fn get_scrambled_message(
             let msg_a = user_a_msg.unwrap();
             let msg_b = user_b_msg.unwrap();
 
-            let content_a = match decrypt((&msg_a.data, &msg_a.nonce), config) {
+            let content_a = match user_message_cache.decrypt_message(user_a.id.get(), msg_a) {
                 Ok(content) => content,
-                Err(e) => return Err(ScramblrError::DecryptionError)
+                Err(_e) => return Err(ScramblrError::DecryptionError)
             };
 
-            let content_b = match decrypt((&msg_b.data, &msg_b.nonce), config) {
+            let content_b = match user_message_cache.decrypt_message(user_b.id.get(), msg_b) {
                 Ok(content) => content,
-                Err(e) => return Err(ScramblrError::DecryptionError)
+                Err(_e) => return Err(ScramblrError::DecryptionError)
             };
 
             if msg_a.id == msg_b.id {
-                // skip iteration if message IDs match
+                // skip iteration if message IDs match, counting it as
+                // a try so a low minimum can't loop forever
+                scramble_tries += 1;
                 continue;
             }
 
@@ -116,6 +141,34 @@ pub fn get_scrambled_message(
     }
 }
 
+/// Returns the messages of `user` that may be shown in `guild_id`:
+/// those sent there, plus those sent in any other guild allowing
+/// cross-guild mixing if `guild_id` allows it too
+fn get_usable_messages(
+    user: &User,
+    guild_id: u64,
+    user_message_cache: &UserMessageCache,
+    settings: &Settings
+) -> Result<Vec<CacheMessage>, ScramblrError> {
+    let messages = if settings.is_cross_guild_allowed(guild_id) {
+        user_message_cache.get_user_messages(user.id.get()).map(|messages| {
+            messages.into_iter()
+                .filter(|msg| match msg.guild_id.as_ref().and_then(|id| id.parse::<u64>().ok()) {
+                    Some(msg_guild_id) => msg_guild_id == guild_id || settings.is_cross_guild_allowed(msg_guild_id),
+                    None => false
+                })
+                .collect()
+        })
+    } else {
+        user_message_cache.get_user_guild_messages(user.id.get(), guild_id)
+    };
+
+    match messages {
+        Ok(messages) => Ok(messages),
+        Err(e) => Err(ScramblrError::CacheError(e.to_string()))
+    }
+}
+
 fn make_scrambled_message(
     msg_a: &str,
     msg_b: &str,
@@ -0,0 +1,362 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serenity::{cache::Cache, model::id::{ChannelId, GuildId}, prelude::{TypeMapKey, RwLock}};
+
+/// Default location of the settings file
+pub const DEFAULT_SETTINGS_PATH: &str = "data/settings.toml";
+
+/// Errors that can occur with the settings file
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    /// Returned when the settings file could
+    /// not be read from file
+    #[error("Could not read settings file: {0}")]
+    ReadError(String),
+
+    /// Returned when the settings file could
+    /// not be parsed
+    #[error("Could not parse settings file: {0}")]
+    ParseError(String),
+
+    /// Returned when the settings file could
+    /// not be written
+    #[error("Could not write settings file: {0}")]
+    WriteError(String)
+}
+
+/// Settings a user has chosen for themselves
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct UserSettings {
+    /// Whether the user's messages may be cached
+    #[serde(default = "default_message_caching")]
+    pub message_caching: bool,
+}
+
+fn default_message_caching() -> bool { true }
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            message_caching: default_message_caching()
+        }
+    }
+}
+
+/// Settings chosen by a guild's admins
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct GuildSettings {
+    /// Whether channels without a rule of their own,
+    /// or in their category, are cached
+    #[serde(default = "default_message_caching")]
+    pub cache_by_default: bool,
+
+    /// Channels and categories whose messages are cached
+    #[serde(default)]
+    pub included_channels: Vec<u64>,
+
+    /// Channels and categories whose messages are never cached
+    #[serde(default)]
+    pub excluded_channels: Vec<u64>,
+
+    /// Whether commands like /scramblr may use messages sent in
+    /// other guilds that allow it too, rather than only this one
+    #[serde(default)]
+    pub allow_cross_guild: bool,
+
+    /// Limits overriding the config file in this guild
+    #[serde(default)]
+    pub limits: GuildLimits,
+
+    /// Command prefixes used instead of the ones in the config file
+    #[serde(default)]
+    pub prefixes: Option<Vec<String>>,
+}
+
+/// Limits a guild has set for itself. Anything left unset
+/// falls back to the value in the config file.
+#[derive(Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+pub struct GuildLimits {
+    /// Overrides `cache.min_words`
+    #[serde(default)]
+    pub min_words: Option<usize>,
+
+    /// Overrides `scramblr.min_messages`
+    #[serde(default)]
+    pub scramblr_min_messages: Option<usize>,
+
+    /// Overrides `scramblr.max_tries`
+    #[serde(default)]
+    pub scramblr_max_tries: Option<usize>,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            cache_by_default: default_message_caching(),
+            included_channels: Vec::new(),
+            excluded_channels: Vec::new(),
+            allow_cross_guild: false,
+            limits: GuildLimits::default(),
+            prefixes: None
+        }
+    }
+}
+
+impl GuildSettings {
+    /// Returns the caching rule set directly on
+    /// `channel_id`, if there is one
+    pub fn get_channel_rule(&self, channel_id: u64) -> Option<bool> {
+        if self.excluded_channels.contains(&channel_id) {
+            Some(false)
+        } else if self.included_channels.contains(&channel_id) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if messages in `channel_id` may be cached.
+    /// 
+    /// A rule on the channel itself wins over a rule on any of
+    /// its `parent_ids` (nearest first, e.g. a thread's channel
+    /// and then its category), which wins over the guild default.
+    pub fn is_channel_cached(&self, channel_id: u64, parent_ids: &[u64]) -> bool {
+        std::iter::once(&channel_id)
+            .chain(parent_ids)
+            .find_map(|id| self.get_channel_rule(*id))
+            .unwrap_or(self.cache_by_default)
+    }
+}
+
+/// Persistent settings chosen by users and guilds
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct Settings {
+    /// Where the settings are saved, if anywhere
+    #[serde(skip)]
+    path: Option<String>,
+
+    // <user_id, settings>
+    #[serde(default)]
+    users: HashMap<String, UserSettings>,
+
+    // <guild_id, settings>
+    #[serde(default)]
+    guilds: HashMap<String, GuildSettings>,
+}
+
+pub struct SettingsData;
+
+impl TypeMapKey for SettingsData {
+    type Value = Arc<RwLock<Settings>>;
+}
+
+impl Settings {
+    /// Reads the settings stored at `path`, starting with defaults
+    /// if the file does not exist yet. Without a `path`, nothing
+    /// is read or written.
+    pub fn open(path: Option<&str>) -> Result<Self, SettingsError> {
+        let mut settings = match path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => match toml::from_str::<Self>(contents.as_str()) {
+                    Ok(settings) => settings,
+                    Err(e) => return Err(SettingsError::ParseError(e.to_string()))
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+                Err(e) => return Err(SettingsError::ReadError(e.to_string()))
+            },
+            None => Self::default()
+        };
+
+        settings.path = path.map(str::to_string);
+
+        Ok(settings)
+    }
+
+    /// Writes the settings to their file
+    pub fn save(&self) -> Result<(), SettingsError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(())
+        };
+
+        let contents = match toml::to_string(self) {
+            Ok(contents) => contents,
+            Err(e) => return Err(SettingsError::WriteError(e.to_string()))
+        };
+
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                return Err(SettingsError::WriteError(e.to_string()));
+            }
+        }
+
+        match std::fs::write(path, contents) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(SettingsError::WriteError(e.to_string()))
+        }
+    }
+
+    /// Returns the settings for `user_id`, or the
+    /// defaults if they haven't changed any
+    pub fn get_user(&self, user_id: u64) -> UserSettings {
+        self.users.get(&user_id.to_string()).cloned().unwrap_or_default()
+    }
+
+    /// Returns `true` if `user_id` allows their messages to be cached
+    pub fn is_caching_enabled(&self, user_id: u64) -> bool {
+        self.get_user(user_id).message_caching
+    }
+
+    /// Opts `user_id` in to or out of message caching, and saves
+    pub fn set_caching_enabled(&mut self, user_id: u64, enabled: bool) -> Result<(), SettingsError> {
+        self.users
+            .entry(user_id.to_string())
+            .or_default()
+            .message_caching = enabled;
+
+        self.save()
+    }
+
+    /// Returns the settings for `guild_id`, or the
+    /// defaults if its admins haven't changed any
+    pub fn get_guild(&self, guild_id: u64) -> GuildSettings {
+        self.guilds.get(&guild_id.to_string()).cloned().unwrap_or_default()
+    }
+
+    /// Returns `true` if messages in `channel_id` of
+    /// `guild_id` may be cached
+    pub fn is_channel_cached(&self, guild_id: u64, channel_id: u64, parent_ids: &[u64]) -> bool {
+        match self.guilds.get(&guild_id.to_string()) {
+            Some(guild) => guild.is_channel_cached(channel_id, parent_ids),
+            None => default_message_caching()
+        }
+    }
+
+    /// Includes a channel or category in caching (`Some(true)`),
+    /// excludes it (`Some(false)`) or clears its rule (`None`),
+    /// and saves
+    pub fn set_channel_rule(&mut self, guild_id: u64, channel_id: u64, rule: Option<bool>) -> Result<(), SettingsError> {
+        let guild = self.guilds.entry(guild_id.to_string()).or_default();
+
+        guild.included_channels.retain(|id| *id != channel_id);
+        guild.excluded_channels.retain(|id| *id != channel_id);
+
+        match rule {
+            Some(true) => guild.included_channels.push(channel_id),
+            Some(false) => guild.excluded_channels.push(channel_id),
+            None => {}
+        }
+
+        self.save()
+    }
+
+    /// Returns `true` if messages sent in `guild_id` may be
+    /// shown in other guilds, and the other way around
+    pub fn is_cross_guild_allowed(&self, guild_id: u64) -> bool {
+        self.get_guild(guild_id).allow_cross_guild
+    }
+
+    /// Sets whether messages may be mixed between `guild_id`
+    /// and other guilds that allow it, and saves
+    pub fn set_cross_guild_allowed(&mut self, guild_id: u64, allowed: bool) -> Result<(), SettingsError> {
+        self.guilds
+            .entry(guild_id.to_string())
+            .or_default()
+            .allow_cross_guild = allowed;
+
+        self.save()
+    }
+
+    /// Returns the limits `guild_id` has overridden
+    pub fn get_guild_limits(&self, guild_id: u64) -> GuildLimits {
+        match self.guilds.get(&guild_id.to_string()) {
+            Some(guild) => guild.limits,
+            None => GuildLimits::default()
+        }
+    }
+
+    /// Replaces the limits `guild_id` has overridden, and saves
+    pub fn set_guild_limits(&mut self, guild_id: u64, limits: GuildLimits) -> Result<(), SettingsError> {
+        self.guilds
+            .entry(guild_id.to_string())
+            .or_default()
+            .limits = limits;
+
+        self.save()
+    }
+
+    /// Returns the command prefixes `guild_id` uses instead
+    /// of the configured ones, if it has set any
+    pub fn get_guild_prefixes(&self, guild_id: u64) -> Option<&Vec<String>> {
+        self.guilds
+            .get(&guild_id.to_string())
+            .and_then(|guild| guild.prefixes.as_ref())
+    }
+
+    /// Sets the command prefixes of `guild_id`, or goes back to
+    /// the configured ones with `None`, and saves
+    pub fn set_guild_prefixes(&mut self, guild_id: u64, prefixes: Option<Vec<String>>) -> Result<(), SettingsError> {
+        self.guilds
+            .entry(guild_id.to_string())
+            .or_default()
+            .prefixes = prefixes;
+
+        self.save()
+    }
+
+    /// Sets whether channels without a rule are cached, and saves
+    pub fn set_cache_by_default(&mut self, guild_id: u64, enabled: bool) -> Result<(), SettingsError> {
+        self.guilds
+            .entry(guild_id.to_string())
+            .or_default()
+            .cache_by_default = enabled;
+
+        self.save()
+    }
+}
+
+/// Returns the ids of the channels `channel_id` sits under, nearest
+/// first: a thread's parent channel, then that channel's category.
+/// 
+/// Channels missing from the cache are treated as having no parents.
+pub fn get_channel_parents(cache: &Cache, guild_id: GuildId, channel_id: ChannelId) -> Vec<u64> {
+    let mut parents = Vec::new();
+
+    let guild = match cache.guild(guild_id) {
+        Some(guild) => guild,
+        None => return parents
+    };
+
+    let mut current = channel_id;
+
+    loop {
+        let channel = guild.channels.get(&current)
+            .or_else(|| guild.threads.iter().find(|thread| thread.id == current));
+
+        match channel.and_then(|channel| channel.parent_id) {
+            Some(parent_id) if !parents.contains(&parent_id.get()) => {
+                parents.push(parent_id.get());
+                current = parent_id;
+            },
+            _ => break
+        }
+    }
+
+    parents
+}
+
+/// Returns every channel and thread of `guild_id` in the cache,
+/// each paired with the ids of the channels it sits under
+pub fn get_guild_channels(cache: &Cache, guild_id: GuildId) -> Vec<(u64, Vec<u64>)> {
+    let channel_ids = match cache.guild(guild_id) {
+        Some(guild) => guild.channels.keys()
+            .chain(guild.threads.iter().map(|thread| &thread.id))
+            .copied()
+            .collect::<Vec<ChannelId>>(),
+        None => return Vec::new()
+    };
+
+    channel_ids.into_iter()
+        .map(|channel_id| (channel_id.get(), get_channel_parents(cache, guild_id, channel_id)))
+        .collect()
+}
@@ -0,0 +1,8 @@
+/// A keyring file path unique to one test, removed first
+/// in case an earlier run left it behind
+pub(crate) fn keyring_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rittou-{name}-{}.toml", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    path.to_string_lossy().to_string()
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use aes_gcm_siv::Aes256GcmSiv;
+
+use crate::encryption::{Keyring, CryptionError, gen_data_key, data_key_cipher};
+
+/// Default location of the user data key file.
+///
+/// This file should not be backed up alongside the message
+/// cache: deleting a user's key from it is what makes their
+/// cached messages unrecoverable.
+pub const DEFAULT_USER_KEYS_PATH: &str = "data/user_keys.toml";
+
+/// A user's data key, encrypted with a keyring key
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct WrappedKey {
+    /// Id of the keyring key that wrapped `key`
+    key_id: u32,
+    key: Vec<u8>,
+    nonce: Vec<u8>
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct UserKeysFile {
+    // <user_id, wrapped key>
+    keys: HashMap<String, WrappedKey>
+}
+
+/// Per-user data keys, each wrapped by the keyring.
+///
+/// Every user's cached messages are encrypted with their own
+/// data key, so destroying that one key crypto-shreds all of
+/// their messages, including copies left in old backups.
+pub struct UserKeyStore {
+    path: Option<String>,
+    keys: HashMap<String, WrappedKey>
+}
+
+impl UserKeyStore {
+    /// Reads the data keys stored at `path`, starting empty if the
+    /// file does not exist yet. Without a `path`, nothing is read
+    /// or written.
+    pub fn open(path: Option<&str>) -> Result<Self, CryptionError> {
+        let file = match path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => match toml::from_str::<UserKeysFile>(contents.as_str()) {
+                    Ok(file) => file,
+                    Err(e) => return Err(CryptionError::KeyringReadFailed(e.to_string()))
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => UserKeysFile::default(),
+                Err(e) => return Err(CryptionError::KeyringReadFailed(e.to_string()))
+            },
+            None => UserKeysFile::default()
+        };
+
+        Ok(Self {
+            path: path.map(str::to_string),
+            keys: file.keys
+        })
+    }
+
+    /// Returns the cipher for `user_id`'s data key, if they have one
+    pub fn get_cipher(&self, user_id: u64, keyring: &Keyring) -> Result<Option<Aes256GcmSiv>, CryptionError> {
+        match self.keys.get(&user_id.to_string()) {
+            Some(wrapped) => {
+                let key = keyring.decrypt_bytes(wrapped.key_id, &wrapped.key, &wrapped.nonce)?;
+
+                Ok(Some(data_key_cipher(&key)?))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Returns the cipher for `user_id`'s data key, generating
+    /// and saving a new key if they don't have one yet
+    pub fn get_or_create_cipher(&mut self, user_id: u64, keyring: &Keyring) -> Result<Aes256GcmSiv, CryptionError> {
+        if let Some(cipher) = self.get_cipher(user_id, keyring)? {
+            return Ok(cipher);
+        }
+
+        let (key, cipher) = gen_data_key();
+        let wrapped = keyring.encrypt_bytes(&key)?;
+
+        self.keys.insert(user_id.to_string(), WrappedKey {
+            key_id: wrapped.key_id,
+            key: wrapped.data,
+            nonce: wrapped.nonce
+        });
+
+        self.save()?;
+
+        Ok(cipher)
+    }
+
+    /// Destroys `user_id`'s data key, making every message
+    /// encrypted with it unrecoverable
+    pub fn destroy_key(&mut self, user_id: u64) -> Result<bool, CryptionError> {
+        if self.keys.remove(&user_id.to_string()).is_none() {
+            return Ok(false);
+        }
+
+        self.save()?;
+
+        Ok(true)
+    }
+
+    /// Re-wraps every data key not wrapped with the keyring's
+    /// current key. Returns the number of keys re-wrapped.
+    pub fn rewrap_keys(&mut self, keyring: &Keyring) -> Result<usize, CryptionError> {
+        let current_key_id = keyring.get_current_key_id();
+        let mut rewrapped = 0;
+
+        for wrapped in self.keys.values_mut() {
+            if wrapped.key_id == current_key_id {
+                continue;
+            }
+
+            let key = keyring.decrypt_bytes(wrapped.key_id, &wrapped.key, &wrapped.nonce)?;
+            let new_wrapped = keyring.encrypt_bytes(&key)?;
+
+            *wrapped = WrappedKey {
+                key_id: new_wrapped.key_id,
+                key: new_wrapped.data,
+                nonce: new_wrapped.nonce
+            };
+
+            rewrapped += 1;
+        }
+
+        if rewrapped > 0 {
+            self.save()?;
+        }
+
+        Ok(rewrapped)
+    }
+
+    fn save(&self) -> Result<(), CryptionError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(())
+        };
+
+        let file = UserKeysFile { keys: self.keys.clone() };
+
+        let contents = match toml::to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) => return Err(CryptionError::KeyringWriteFailed(e.to_string()))
+        };
+
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                return Err(CryptionError::KeyringWriteFailed(e.to_string()));
+            }
+        }
+
+        match std::fs::write(path, contents) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(CryptionError::KeyringWriteFailed(e.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{decrypt_with, encrypt_with};
+    use crate::test_util::keyring_path;
+
+    #[test]
+    fn data_key_is_reused() {
+        let keyring = Keyring::open(None, "secret", &[]).unwrap();
+        let mut user_keys = UserKeyStore::open(None).unwrap();
+
+        let cipher = user_keys.get_or_create_cipher(1, &keyring).unwrap();
+        let (data, nonce) = encrypt_with(&cipher, b"hello").unwrap();
+
+        let cipher = user_keys.get_cipher(1, &keyring).unwrap().expect("Expected a data key");
+        assert_eq!(decrypt_with(&cipher, &data, &nonce).unwrap(), b"hello");
+        assert!(user_keys.get_cipher(2, &keyring).unwrap().is_none());
+    }
+
+    #[test]
+    fn rewrapped_keys_still_decrypt() {
+        let path = keyring_path("rewrap");
+
+        let old = Keyring::open(Some(&path), "old secret", &[]).unwrap();
+        let mut user_keys = UserKeyStore::open(None).unwrap();
+
+        let cipher = user_keys.get_or_create_cipher(1, &old).unwrap();
+        let (data, nonce) = encrypt_with(&cipher, b"hello").unwrap();
+
+        let rotated = Keyring::open(Some(&path), "new secret", &["old secret".to_string()]).unwrap();
+        assert_eq!(user_keys.rewrap_keys(&rotated).unwrap(), 1);
+        assert_eq!(user_keys.rewrap_keys(&rotated).unwrap(), 0);
+
+        // the old secret is no longer needed
+        let dropped = Keyring::open(Some(&path), "new secret", &[]).unwrap();
+        let cipher = user_keys.get_cipher(1, &dropped).unwrap().expect("Expected a data key");
+        assert_eq!(decrypt_with(&cipher, &data, &nonce).unwrap(), b"hello");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn destroyed_key_is_gone() {
+        let keyring = Keyring::open(None, "secret", &[]).unwrap();
+        let mut user_keys = UserKeyStore::open(None).unwrap();
+
+        user_keys.get_or_create_cipher(1, &keyring).unwrap();
+
+        assert!(user_keys.destroy_key(1).unwrap());
+        assert!(!user_keys.destroy_key(1).unwrap());
+        assert!(user_keys.get_cipher(1, &keyring).unwrap().is_none());
+    }
+}
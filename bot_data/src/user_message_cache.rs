@@ -2,17 +2,130 @@ use std::{collections::HashMap, sync::Arc};
 
 use serde::{Serialize, Deserialize};
 use serenity::{model::prelude::Message, prelude::{TypeMapKey, RwLock}};
+use tokio::sync::Notify;
 use url::Url;
 
-use crate::{encryption, config::Config};
+use crate::{
+    encryption::{Keyring, CryptionError, encrypt_with, decrypt_with},
+    user_keys::{UserKeyStore, DEFAULT_USER_KEYS_PATH},
+    config::{Config, CacheConfig, CacheBackend},
+    settings::Settings
+};
+
+mod flusher;
+mod memory_store;
+mod migrations;
+mod polodb_store;
+mod retention;
+mod toml_store;
+
+pub use flusher::spawn_flush_task;
+pub use migrations::{read_cache, CURRENT_CACHE_VERSION};
+pub use memory_store::InMemoryStore;
+pub use polodb_store::PoloDbStore;
+pub use retention::{RetentionPolicy, RetentionIndex, MessageKey, DEFAULT_MAX_USER_MESSAGES};
+pub use toml_store::TomlFileStore;
+
+/// Default location of the TOML message cache file
+pub const DEFAULT_TOML_PATH: &str = "data/messages.toml";
+
+/// Default location of the message cache database
+pub const DEFAULT_DATABASE_PATH: &str = "data/messages.db";
+
+/// Storage backend for cached messages.
+/// 
+/// Implementations own the messages themselves, while
+/// `UserMessageCache` decides what gets stored and when
+/// it gets evicted.
+pub trait MessageStore: Send + Sync {
+    /// Stores a new message sent by `user_id`
+    fn add_message(&mut self, user_id: u64, message: CacheMessage) -> Result<(), MessageCacheError>;
+
+    /// Replaces a stored message with the same id.
+    /// 
+    /// Returns `false` if no such message was stored.
+    fn update_message(&mut self, user_id: u64, message: CacheMessage) -> Result<bool, MessageCacheError>;
+
+    /// Removes a single message
+    fn remove_message(&mut self, user_id: u64, channel_id: u64, message_id: u64) -> Result<(), MessageCacheError>;
+
+    /// Removes every message sent in `channel_id`
+    fn remove_messages_in_channel(&mut self, channel_id: u64) -> Result<(), MessageCacheError>;
+
+    /// Removes every message sent by `user_id`
+    fn remove_user_messages(&mut self, user_id: u64) -> Result<(), MessageCacheError>;
+
+    /// Removes every message sent in `guild_id`
+    fn remove_guild_messages(&mut self, guild_id: u64) -> Result<(), MessageCacheError>;
+
+    /// Returns every message sent by `user_id`, across all channels
+    fn get_user_messages(&self, user_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError>;
+
+    /// Returns every message sent by `user_id` in `guild_id`
+    fn get_user_guild_messages(&self, user_id: u64, guild_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError> {
+        let guild_id = guild_id.to_string();
+
+        Ok(self.get_user_messages(user_id)?
+            .into_iter()
+            .filter(|msg| msg.guild_id.as_ref() == Some(&guild_id))
+            .collect())
+    }
+
+    /// Returns every message sent in `channel_id`,
+    /// paired with the id of its author
+    fn get_channel_messages(&self, channel_id: u64) -> Result<Vec<(u64, CacheMessage)>, MessageCacheError>;
+
+    /// Returns every stored message, paired with the id of its author
+    fn get_all_messages(&self) -> Result<Vec<(u64, CacheMessage)>, MessageCacheError>;
+
+    /// Reads previously persisted messages, if the
+    /// backend persists anything
+    fn load(&mut self) -> Result<(), MessageCacheError> { Ok(()) }
+
+    /// Persists any changes not yet written out
+    fn flush(&self) -> Result<(), MessageCacheError> { Ok(()) }
+}
+
+/// Opens the message store selected by the `[cache]` config section
+pub fn open_store(cache_config: &CacheConfig) -> Result<Box<dyn MessageStore>, MessageCacheError> {
+    match cache_config.get_backend() {
+        CacheBackend::Memory => Ok(Box::new(InMemoryStore::new())),
+        CacheBackend::Toml => {
+            let path = cache_config.get_path().unwrap_or(DEFAULT_TOML_PATH);
+
+            Ok(Box::new(TomlFileStore::new(path)))
+        },
+        CacheBackend::PoloDb => {
+            let path = cache_config.get_path().unwrap_or(DEFAULT_DATABASE_PATH);
+            let toml_path = cache_config.get_toml_path().unwrap_or(DEFAULT_TOML_PATH);
+
+            Ok(Box::new(PoloDbStore::open(path, toml_path)?))
+        }
+    }
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct CacheMessage {
     pub id: String,
     pub channel_id: String,
+
+    /// Guild the message was sent in. Messages cached before
+    /// guilds were recorded have none until their channel's
+    /// guild is seen again
+    #[serde(default)]
+    pub guild_id: Option<String>,
+
     pub time: i64,
     pub data: Vec<u8>,
-    pub nonce: String,
+    pub nonce: Vec<u8>,
+
+    /// Id of the keyring key `data` was encrypted with, or the
+    /// current keyring key when it was stored if `envelope` is set
+    pub key_id: u32,
+
+    /// Whether `data` is encrypted with the author's
+    /// data key rather than directly with the keyring
+    pub envelope: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -26,16 +139,41 @@ pub struct MessageCacheData {
 impl MessageCacheData {
     pub fn new() -> Self {
         Self {
-            version: 1,
+            version: CURRENT_CACHE_VERSION,
             data: HashMap::new()
         }
     }
 }
 
-#[derive(Clone)]
 pub struct UserMessageCache {
-    pub max_msgs: usize,
-    pub messages: MessageCacheData
+    /// Where cached messages are kept
+    store: Box<dyn MessageStore>,
+
+    /// Limits on which messages are kept
+    retention_policy: RetentionPolicy,
+
+    /// Every message in `store`, oldest first
+    retention: RetentionIndex,
+
+    /// Fewest words a message needs to be cached,
+    /// unless its guild overrides it
+    min_words: usize,
+
+    /// Keys that wrap each user's data key
+    keyring: Keyring,
+
+    /// Per-user data keys cached messages are encrypted with
+    user_keys: UserKeyStore,
+
+    /// Writes made since the store was last flushed
+    dirty_writes: usize,
+
+    /// Number of dirty writes that requests an early flush,
+    /// or `0` to only flush on the background interval
+    flush_after_writes: usize,
+
+    /// Signalled once `flush_after_writes` is reached
+    flush_requested: Arc<Notify>
 }
 
 pub struct UserMessageData;
@@ -66,53 +204,144 @@ pub enum MessageCacheError {
 
     #[error("An error occurred while writing file")]
     FileWriteError,
+
+    #[error("An error occurred while accessing the message database: {0}")]
+    DatabaseError(String),
+
+    #[error("Message cache version {0} is newer than the supported version {CURRENT_CACHE_VERSION}")]
+    UnsupportedVersion(u16),
+
+    #[error("An error occurred while migrating the message cache: {0}")]
+    MigrationError(String),
 }
 
 impl UserMessageCache {
-    pub fn new() -> Self {
+    /// Creates a cache on top of the given message store, encrypting
+    /// messages with data keys from `user_keys` wrapped by `keyring`
+    pub fn with_store(store: Box<dyn MessageStore>, keyring: Keyring, user_keys: UserKeyStore) -> Self {
         Self {
-            max_msgs: 200,
-            messages: MessageCacheData::new()
+            store,
+            retention_policy: RetentionPolicy::default(),
+            retention: RetentionIndex::new(),
+            min_words: CacheConfig::default().get_min_words(),
+            keyring,
+            user_keys,
+            dirty_writes: 0,
+            flush_after_writes: 0,
+            flush_requested: Arc::new(Notify::new())
         }
     }
 
+    /// Creates a cache using the backend selected in `config`
+    pub fn from_config(config: &Config) -> Result<Self, MessageCacheError> {
+        let keyring = match Keyring::from_config(config) {
+            Ok(keyring) => keyring,
+            Err(e) => return Err(MessageCacheError::CryptionError(e.to_string()))
+        };
+
+        let user_keys = match UserKeyStore::open(Some(DEFAULT_USER_KEYS_PATH)) {
+            Ok(user_keys) => user_keys,
+            Err(e) => return Err(MessageCacheError::CryptionError(e.to_string()))
+        };
+
+        let mut cache = Self::with_store(open_store(config.get_cache_config())?, keyring, user_keys);
+        cache.apply_limits(config.get_cache_config());
+
+        Ok(cache)
+    }
+
+    /// Persists the cache through its store
     pub fn save_cache(&self) -> Result<(), MessageCacheError> {
-        match toml::to_string(&self.messages) {
-            Ok(data) => {
-                match std::fs::create_dir_all("data") {
-                    Ok(_) => {
-                        if let Err(_e) = std::fs::write("data/messages.toml", data) {
-                            Err(MessageCacheError::FileWriteError)
-                        } else {
-                            Ok(())
-                        }
-                    },
-                    Err(e) => {
-                        println!("{e:?}");
-                        Err(MessageCacheError::PathCreateError)
-                    }
-                }
-            },
-            Err(_e) => {
-                Err(MessageCacheError::TomlConvertError)
-            }
+        self.store.flush()
+    }
+
+    /// Persists the cache if anything changed since the last flush
+    pub fn flush(&mut self) -> Result<(), MessageCacheError> {
+        if self.dirty_writes == 0 {
+            return Ok(());
+        }
+
+        self.save_cache()?;
+        self.dirty_writes = 0;
+
+        Ok(())
+    }
+
+    /// Returns the number of writes made since the last flush
+    pub fn get_dirty_writes(&self) -> usize { self.dirty_writes }
+
+    /// Returns a handle that is notified whenever enough
+    /// dirty writes pile up to warrant an early flush
+    pub fn flush_requested(&self) -> Arc<Notify> {
+        self.flush_requested.clone()
+    }
+
+    fn mark_dirty(&mut self) {
+        self.dirty_writes += 1;
+
+        if self.flush_after_writes > 0 && self.dirty_writes >= self.flush_after_writes {
+            self.flush_requested.notify_one();
         }
     }
 
+    /// Reloads the cache from its store, then evicts
+    /// anything the retention policy no longer allows
     pub fn load_cache(&mut self) -> Result<(), MessageCacheError> {
-        if let Ok(contents) = std::fs::read_to_string("data/messages.toml") {
-            if let Ok(cache) = toml::from_str::<MessageCacheData>(contents.as_str()) {
-                self.messages = cache;
-                Ok(())
-            } else {
-                Err(MessageCacheError::TomlParseError)
-            }
-        } else {
-            Err(MessageCacheError::TomlReadError)
+        self.store.load()?;
+        self.retention = RetentionIndex::from_messages(self.store.get_all_messages()?);
+
+        self.apply_retention()?;
+
+        Ok(())
+    }
+
+    /// Applies the limits in a `[cache]` config section. The new
+    /// retention policy is applied on the next write or
+    /// `apply_retention` call.
+    pub fn apply_limits(&mut self, cache_config: &CacheConfig) {
+        self.flush_after_writes = cache_config.get_flush_after_writes();
+        self.min_words = cache_config.get_min_words();
+        self.retention_policy = RetentionPolicy {
+            max_user_messages: cache_config.get_max_user_messages(),
+            max_guild_messages: cache_config.get_max_guild_messages(),
+            max_age: cache_config.get_max_age(),
+            max_bytes: cache_config.get_max_bytes()
+        };
+    }
+
+    pub fn get_retention_policy(&self) -> &RetentionPolicy { &self.retention_policy }
+
+    /// Replaces the retention policy. It is applied
+    /// on the next write or `apply_retention` call.
+    pub fn set_retention_policy(&mut self, policy: RetentionPolicy) {
+        self.retention_policy = policy;
+    }
+
+    /// Evicts every message the retention policy no longer allows,
+    /// oldest first. Returns the number of messages evicted.
+    pub fn apply_retention(&mut self) -> Result<usize, MessageCacheError> {
+        let now = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+            Ok(now) => i64::try_from(now.as_secs()).unwrap_or(i64::MAX),
+            Err(_e) => 0
+        };
+
+        let evicted = self.retention.evict(&self.retention_policy, now);
+
+        for key in &evicted {
+            self.store.remove_message(key.user_id, key.channel_id, key.message_id)?;
+        }
+
+        if !evicted.is_empty() {
+            self.mark_dirty();
         }
+
+        Ok(evicted.len())
     }
 
-    pub fn add_or_update_msg(&mut self, message: &Message, config: &Config) -> Result<(), MessageCacheError> {
+    /// Caches `message`, or updates its cached copy. `parent_ids` are
+    /// the channels the message's channel sits under, nearest first,
+    /// used to apply the guild's caching rules for categories
+    pub fn add_or_update_msg(&mut self, message: &Message, settings: &Settings, parent_ids: &[u64]) -> Result<(), MessageCacheError> {
         let mut msg_content = message.content.clone();
 
         // skip cache if the message is a dm or from a bot
@@ -136,16 +365,33 @@ impl UserMessageCache {
             msg_content = split_msg.join(" ");
         }
 
+        let min_words = message.guild_id
+            .and_then(|guild_id| settings.get_guild_limits(guild_id.get()).min_words)
+            .unwrap_or(self.min_words);
+
         // skip if the message is too short
-        if split_msg.len() < 3 {
+        if split_msg.len() < min_words {
             return Ok(());   
         }
 
         // check if a channel should be cached
+        if let Some(guild_id) = message.guild_id {
+            if !settings.is_channel_cached(guild_id.get(), message.channel_id.get(), parent_ids) {
+                return Ok(());
+            }
+        }
 
         // check if a user has message caching enabled
+        if !settings.is_caching_enabled(message.author.id.get()) {
+            return Ok(());
+        }
+
+        let user_id = message.author.id.get();
 
-        let (enc_data, nonce) = match encryption::encrypt(&message.content, config) {
+        let encrypted = self.user_keys.get_or_create_cipher(user_id, &self.keyring)
+            .and_then(|cipher| encrypt_with(&cipher, message.content.as_bytes()));
+
+        let (data, nonce) = match encrypted {
             Ok(res) => {
                 res
             },
@@ -154,105 +400,200 @@ impl UserMessageCache {
             }
         };
 
+        let cache_message = CacheMessage {
+            id: message.id.get().to_string(),
+            channel_id: message.channel_id.get().to_string(),
+            guild_id: message.guild_id.map(|guild_id| guild_id.get().to_string()),
+            time: message.timestamp.unix_timestamp(),
+            data,
+            nonce,
+            key_id: self.keyring.get_current_key_id(),
+            envelope: true
+        };
+
         // find and modify an existing message,
         // or add a new one 
-        self.messages.data
-            .entry(message.author.id.get().to_string())
-            .or_insert(HashMap::new())
-            .entry(message.channel_id.get().to_string())
-            .and_modify(|messages| {
-                if let Some(msg) = messages.iter_mut().find(|msg|
-                    msg.id == message.id.get().to_string()
-                ) {
-                    msg.data = enc_data.clone();
-                    msg.time = message.timestamp.unix_timestamp();
-                } else {
-                    messages.push(CacheMessage {
-                        id: message.id.get().to_string(),
-                        channel_id: message.channel_id.get().to_string(),
-                        time: message.timestamp.unix_timestamp(),
-                        data: enc_data.clone(),
-                        nonce: nonce.clone()
-                    })
-                }
-            })
-            .or_insert(vec![
-                CacheMessage {
-                    id: message.id.get().to_string(),
-                    channel_id: message.channel_id.get().to_string(),
-                    time: message.timestamp.unix_timestamp(),
-                    data: enc_data.clone(),
-                    nonce: nonce.clone()
-                }
-            ]);
-        
-
-        if let Some(mut user_messages) = self.get_user_messages_mut(message.author.id.get()) {
-            user_messages.sort_by(|msg_a, msg_b| msg_a.time.cmp(&msg_b.time));
-
-            if user_messages.len() > self.max_msgs {
-                let amt_msgs_to_remove = user_messages.len() - self.max_msgs;
-
-                for index in 0..amt_msgs_to_remove {
-                    self.messages.data
-                        .get_mut(&message.author.id.get().to_string())
-                        .unwrap()
-                        .get_mut(&message.channel_id.get().to_string())
-                        .unwrap()
-                        .remove(index);
-                }
-            }
+        if !self.store.update_message(user_id, cache_message.clone())? {
+            self.store.add_message(user_id, cache_message.clone())?;
         }
 
+        self.retention.insert(user_id, &cache_message);
+        self.mark_dirty();
+
+        self.apply_retention()?;
+
         Ok(())
     }
 
-    pub fn get_user_messages(&self, user_id: u64) -> Option<Vec<&CacheMessage>> {
-        if let Some(msgs) = self.messages.data.get(&user_id.to_string()) {
-            let mut user_messages = Vec::new();
+    /// Returns every cached message sent by `user_id`
+    pub fn get_user_messages(&self, user_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError> {
+        self.store.get_user_messages(user_id)
+    }
+
+    /// Returns every cached message sent by `user_id` in `guild_id`
+    pub fn get_user_guild_messages(&self, user_id: u64, guild_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError> {
+        self.store.get_user_guild_messages(user_id, guild_id)
+    }
+
+    /// Records `guild_id` on messages cached from its `channel_ids`
+    /// before guilds were recorded. Returns the number of messages
+    /// updated.
+    pub fn assign_guild(&mut self, guild_id: u64, channel_ids: &[u64]) -> Result<usize, MessageCacheError> {
+        let mut assigned = 0;
+
+        for channel_id in channel_ids {
+            for (user_id, mut message) in self.store.get_channel_messages(*channel_id)? {
+                if message.guild_id.is_some() {
+                    continue;
+                }
+
+                message.guild_id = Some(guild_id.to_string());
 
-            for (_channel_id, messages) in msgs {
-                user_messages.extend(messages.iter());
+                self.store.update_message(user_id, message.clone())?;
+                self.retention.insert(user_id, &message);
+                assigned += 1;
             }
+        }
 
-            Some(user_messages)
-        } else {
-            None
+        if assigned > 0 {
+            self.mark_dirty();
         }
+
+        Ok(assigned)
     }
 
-    pub fn get_user_messages_mut(&self, user_id: u64) -> Option<Vec<&CacheMessage>> {
-        if self.messages.data.contains_key(&user_id.to_string()) {
-            let mut user_messages = Vec::new();
+    /// Decrypts the content of a message cached for `user_id`
+    pub fn decrypt_message(&self, user_id: u64, message: &CacheMessage) -> Result<String, CryptionError> {
+        let plaintext = if message.envelope {
+            match self.user_keys.get_cipher(user_id, &self.keyring)? {
+                Some(cipher) => decrypt_with(&cipher, &message.data, &message.nonce)?,
+                None => return Err(CryptionError::MissingDataKey(user_id))
+            }
+        } else {
+            self.keyring.decrypt_bytes(message.key_id, &message.data, &message.nonce)?
+        };
 
-            for messages_map in self.messages.data.values() {
-                for messages in messages_map.values() {
-                    user_messages.extend(messages.iter());
-                }
+        Ok(String::from_utf8_lossy(&plaintext).to_string())
+    }
+
+    /// Re-wraps every data key under the current secret key, and moves
+    /// messages encrypted directly with the keyring over to their
+    /// author's data key, so retired secret keys can be dropped
+    /// from the config.
+    /// 
+    /// Returns the number of data keys re-wrapped and
+    /// the number of messages re-encrypted.
+    pub fn rotate_keys(&mut self) -> Result<(usize, usize), MessageCacheError> {
+        let rewrapped = match self.user_keys.rewrap_keys(&self.keyring) {
+            Ok(rewrapped) => rewrapped,
+            Err(e) => return Err(MessageCacheError::CryptionError(e.to_string()))
+        };
+
+        let mut reencrypted = 0;
+
+        for (user_id, mut message) in self.store.get_all_messages()? {
+            if message.envelope {
+                continue;
             }
 
-            Some(user_messages.clone())
-        } else {
-            None
+            let encrypted = self.decrypt_message(user_id, &message)
+                .and_then(|text| {
+                    let cipher = self.user_keys.get_or_create_cipher(user_id, &self.keyring)?;
+
+                    encrypt_with(&cipher, text.as_bytes())
+                });
+
+            let (data, nonce) = match encrypted {
+                Ok(encrypted) => encrypted,
+                Err(e) => return Err(MessageCacheError::CryptionError(e.to_string()))
+            };
+
+            message.data = data;
+            message.nonce = nonce;
+            message.key_id = self.keyring.get_current_key_id();
+            message.envelope = true;
+
+            self.store.update_message(user_id, message.clone())?;
+            self.retention.insert(user_id, &message);
+            self.mark_dirty();
+
+            reencrypted += 1;
         }
+
+        Ok((rewrapped, reencrypted))
     }
 
-    pub fn remove_message(&mut self, message: &Message) {
-        self.remove_message_by_id(message.author.id.get(), message.channel_id.get(), message.id.get());
+    /// Removes every message cached for `user_id` and destroys their
+    /// data key, so copies of their messages in old backups can
+    /// no longer be decrypted either
+    pub fn forget_user(&mut self, user_id: u64) -> Result<(), MessageCacheError> {
+        self.store.remove_user_messages(user_id)?;
+        self.retention.remove_user(user_id);
+        self.mark_dirty();
+
+        // flush right away so the removal can't be lost
+        // once the key is gone
+        self.flush()?;
+
+        if let Err(e) = self.user_keys.destroy_key(user_id) {
+            return Err(MessageCacheError::CryptionError(e.to_string()));
+        }
+
+        Ok(())
     }
 
-    pub fn remove_message_by_id(&mut self, user_id: u64, channel_id: u64, message_id: u64) {
-        if let Some(msgs) = self.messages.data.get_mut(&user_id.to_string()) {
-            if let Some(values) = msgs.get_mut(&channel_id.to_string()) {
-                values.retain(|msg| msg.id != message_id.to_string());
-            }
+    pub fn remove_message(&mut self, message: &Message) -> Result<(), MessageCacheError> {
+        self.remove_message_by_id(message.author.id.get(), message.channel_id.get(), message.id.get())
+    }
+
+    pub fn remove_message_by_id(&mut self, user_id: u64, channel_id: u64, message_id: u64) -> Result<(), MessageCacheError> {
+        self.store.remove_message(user_id, channel_id, message_id)?;
+        self.retention.remove(&MessageKey { user_id, channel_id, message_id });
+        self.mark_dirty();
+
+        Ok(())
+    }
+
+    pub fn remove_messages_in_channel(&mut self, channel_id: u64) -> Result<(), MessageCacheError> {
+        self.store.remove_messages_in_channel(channel_id)?;
+        self.retention.remove_channel(channel_id);
+        self.mark_dirty();
+
+        Ok(())
+    }
+
+    /// Removes messages deleted from `channel_id` when only their ids
+    /// are known, looking up who wrote them in the cache itself.
+    /// Returns the number of messages removed.
+    pub fn remove_deleted_messages(&mut self, channel_id: u64, message_ids: &[u64]) -> Result<usize, MessageCacheError> {
+        let cached = self.store.get_channel_messages(channel_id)?
+            .into_iter()
+            .filter_map(|(user_id, msg)| match msg.id.parse::<u64>() {
+                Ok(message_id) if message_ids.contains(&message_id) => Some((user_id, message_id)),
+                _ => None
+            })
+            .collect::<Vec<(u64, u64)>>();
+
+        for (user_id, message_id) in &cached {
+            self.remove_message_by_id(*user_id, channel_id, *message_id)?;
         }
+
+        Ok(cached.len())
     }
 
-    pub fn remove_messages_in_channel(&mut self, channel_id: u64) {
-        for (_, user_messages) in &mut self.messages.data {
-            user_messages.remove(&channel_id.to_string());
+    /// Removes every message cached from `guild_id`, e.g. when the
+    /// bot leaves it. Its `channel_ids` are also cleared, in case
+    /// any messages from before guilds were recorded remain.
+    pub fn remove_messages_in_guild(&mut self, guild_id: u64, channel_ids: &[u64]) -> Result<(), MessageCacheError> {
+        self.store.remove_guild_messages(guild_id)?;
+        self.retention.remove_guild(guild_id);
+        self.mark_dirty();
+
+        for channel_id in channel_ids {
+            self.remove_messages_in_channel(*channel_id)?;
         }
+
+        Ok(())
     }
 }
 
@@ -275,4 +616,67 @@ fn string_has_url(content: &str) -> Option<Vec<usize>> {
     } else {
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> UserMessageCache {
+        UserMessageCache::with_store(
+            Box::new(InMemoryStore::new()),
+            Keyring::open(None, "secret", &[]).unwrap(),
+            UserKeyStore::open(None).unwrap()
+        )
+    }
+
+    fn message(id: u64, data: Vec<u8>, nonce: Vec<u8>, key_id: u32, envelope: bool) -> CacheMessage {
+        CacheMessage {
+            id: id.to_string(),
+            channel_id: "10".to_string(),
+            guild_id: Some("100".to_string()),
+            time: id as i64,
+            data,
+            nonce,
+            key_id,
+            envelope
+        }
+    }
+
+    #[test]
+    fn rotation_moves_messages_to_data_keys() {
+        let mut cache = cache();
+
+        let encrypted = cache.keyring.encrypt("keyring message").unwrap();
+        cache.store.add_message(1, message(1, encrypted.data, encrypted.nonce, encrypted.key_id, false)).unwrap();
+
+        assert_eq!(cache.rotate_keys().unwrap(), (0, 1));
+
+        let rotated = &cache.get_user_messages(1).unwrap()[0];
+        assert!(rotated.envelope);
+        assert_eq!(rotated.key_id, cache.keyring.get_current_key_id());
+        assert_eq!(cache.decrypt_message(1, rotated).unwrap(), "keyring message");
+
+        // nothing left to move
+        assert_eq!(cache.rotate_keys().unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn forgotten_user_is_unreadable() {
+        let mut cache = cache();
+
+        let cipher = cache.user_keys.get_or_create_cipher(1, &cache.keyring).unwrap();
+        let (data, nonce) = encrypt_with(&cipher, b"secret message").unwrap();
+        let kept = message(1, data, nonce, cache.keyring.get_current_key_id(), true);
+
+        cache.store.add_message(1, kept.clone()).unwrap();
+        assert_eq!(cache.decrypt_message(1, &kept).unwrap(), "secret message");
+
+        cache.forget_user(1).unwrap();
+
+        assert!(cache.get_user_messages(1).unwrap().is_empty());
+
+        // a copy kept elsewhere, e.g. in a backup, can't be decrypted either
+        assert!(matches!(cache.decrypt_message(1, &kept), Err(CryptionError::MissingDataKey(1))));
+    }
+}
@@ -0,0 +1,46 @@
+use std::{sync::Arc, time::Duration};
+
+use serenity::prelude::RwLock;
+use tokio::task::JoinHandle;
+
+use super::UserMessageCache;
+
+/// Spawns a task that flushes `cache` every `flush_interval`,
+/// and whenever the cache asks for an early flush after too
+/// many dirty writes. A zero interval disables the timer.
+/// 
+/// The retention policy is applied before each flush, so
+/// messages past their maximum age don't outlive the next one.
+pub fn spawn_flush_task(cache: Arc<RwLock<UserMessageCache>>, flush_interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let flush_requested = cache.read().await.flush_requested();
+
+        let mut interval = if flush_interval.is_zero() {
+            None
+        } else {
+            Some(tokio::time::interval(flush_interval))
+        };
+
+        loop {
+            match &mut interval {
+                Some(interval) => {
+                    tokio::select! {
+                        _ = interval.tick() => {},
+                        _ = flush_requested.notified() => {}
+                    }
+                },
+                None => flush_requested.notified().await
+            }
+
+            let mut user_message_cache = cache.write().await;
+
+            if let Err(e) = user_message_cache.apply_retention() {
+                println!("Could not apply message retention: {e}");
+            }
+
+            if let Err(e) = user_message_cache.flush() {
+                println!("Could not flush message cache: {e}");
+            }
+        }
+    })
+}
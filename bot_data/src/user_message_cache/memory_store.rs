@@ -0,0 +1,136 @@
+use super::{CacheMessage, MessageCacheData, MessageCacheError, MessageStore};
+
+/// Message storage that only lives in memory, and
+/// is lost once the bot shuts down
+pub struct InMemoryStore {
+    messages: MessageCacheData
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::from_data(MessageCacheData::new())
+    }
+
+    /// Creates a store holding the messages in `messages`
+    pub fn from_data(messages: MessageCacheData) -> Self {
+        Self { messages }
+    }
+
+    /// Returns the stored messages in their
+    /// serializable layout
+    pub fn data(&self) -> &MessageCacheData {
+        &self.messages
+    }
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageStore for InMemoryStore {
+    fn add_message(&mut self, user_id: u64, message: CacheMessage) -> Result<(), MessageCacheError> {
+        self.messages.data
+            .entry(user_id.to_string())
+            .or_default()
+            .entry(message.channel_id.clone())
+            .or_default()
+            .push(message);
+
+        Ok(())
+    }
+
+    fn update_message(&mut self, user_id: u64, message: CacheMessage) -> Result<bool, MessageCacheError> {
+        let stored = self.messages.data
+            .get_mut(&user_id.to_string())
+            .and_then(|channels| channels.get_mut(&message.channel_id))
+            .and_then(|messages| messages.iter_mut().find(|msg| msg.id == message.id));
+
+        match stored {
+            Some(stored) => {
+                *stored = message;
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+
+    fn remove_message(&mut self, user_id: u64, channel_id: u64, message_id: u64) -> Result<(), MessageCacheError> {
+        if let Some(msgs) = self.messages.data.get_mut(&user_id.to_string()) {
+            if let Some(values) = msgs.get_mut(&channel_id.to_string()) {
+                values.retain(|msg| msg.id != message_id.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_messages_in_channel(&mut self, channel_id: u64) -> Result<(), MessageCacheError> {
+        for user_messages in self.messages.data.values_mut() {
+            user_messages.remove(&channel_id.to_string());
+        }
+
+        Ok(())
+    }
+
+    fn remove_guild_messages(&mut self, guild_id: u64) -> Result<(), MessageCacheError> {
+        let guild_id = guild_id.to_string();
+
+        for user_messages in self.messages.data.values_mut() {
+            for messages in user_messages.values_mut() {
+                messages.retain(|msg| msg.guild_id.as_ref() != Some(&guild_id));
+            }
+
+            user_messages.retain(|_, messages| !messages.is_empty());
+        }
+
+        Ok(())
+    }
+
+    fn remove_user_messages(&mut self, user_id: u64) -> Result<(), MessageCacheError> {
+        self.messages.data.remove(&user_id.to_string());
+
+        Ok(())
+    }
+
+    fn get_user_messages(&self, user_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError> {
+        let mut user_messages = Vec::new();
+
+        if let Some(msgs) = self.messages.data.get(&user_id.to_string()) {
+            for messages in msgs.values() {
+                user_messages.extend(messages.iter().cloned());
+            }
+        }
+
+        Ok(user_messages)
+    }
+
+    fn get_channel_messages(&self, channel_id: u64) -> Result<Vec<(u64, CacheMessage)>, MessageCacheError> {
+        let mut channel_messages = Vec::new();
+
+        for (user_id, msgs) in &self.messages.data {
+            if let Some(messages) = msgs.get(&channel_id.to_string()) {
+                let user_id = user_id.parse().unwrap_or_default();
+
+                channel_messages.extend(messages.iter().map(|msg| (user_id, msg.clone())));
+            }
+        }
+
+        Ok(channel_messages)
+    }
+
+    fn get_all_messages(&self) -> Result<Vec<(u64, CacheMessage)>, MessageCacheError> {
+        let mut all_messages = Vec::new();
+
+        for (user_id, msgs) in &self.messages.data {
+            let user_id = user_id.parse().unwrap_or_default();
+
+            for messages in msgs.values() {
+                all_messages.extend(messages.iter().map(|msg| (user_id, msg.clone())));
+            }
+        }
+
+        Ok(all_messages)
+    }
+}
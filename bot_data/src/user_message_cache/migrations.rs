@@ -0,0 +1,278 @@
+use toml::{Table, Value};
+
+use crate::encryption::LEGACY_KEY_ID;
+
+use super::{MessageCacheData, MessageCacheError};
+
+/// Version of the `MessageCacheData` layout written by this build
+pub const CURRENT_CACHE_VERSION: u16 = 4;
+
+/// A single step upgrading a cache file from version
+/// `from` to version `from + 1`
+struct Migration {
+    from: u16,
+    migrate: fn(&mut Table) -> Result<(), MessageCacheError>
+}
+
+/// Every known migration, in order
+const MIGRATIONS: &[Migration] = &[
+    Migration { from: 1, migrate: add_key_ids },
+    Migration { from: 2, migrate: add_envelope_flags },
+    Migration { from: 3, migrate: add_guild_ids },
+];
+
+/// Parses a TOML cache file of any supported version,
+/// upgrading it step by step to `CURRENT_CACHE_VERSION`.
+/// 
+/// Returns `MessageCacheError::UnsupportedVersion` if the file
+/// was written by a newer version of the bot.
+pub fn read_cache(contents: &str) -> Result<MessageCacheData, MessageCacheError> {
+    let mut table = match contents.parse::<Table>() {
+        Ok(table) => table,
+        Err(_e) => return Err(MessageCacheError::TomlParseError)
+    };
+
+    let mut version = match table.get("version") {
+        Some(Value::Integer(version)) => match u16::try_from(*version) {
+            Ok(version) => version,
+            Err(_e) => return Err(MessageCacheError::TomlParseError)
+        },
+        Some(_) => return Err(MessageCacheError::TomlParseError),
+        // the version field has been written since the first layout
+        None => 1
+    };
+
+    if version > CURRENT_CACHE_VERSION {
+        return Err(MessageCacheError::UnsupportedVersion(version));
+    }
+
+    while version < CURRENT_CACHE_VERSION {
+        let migration = match MIGRATIONS.iter().find(|migration| migration.from == version) {
+            Some(migration) => migration,
+            None => return Err(MessageCacheError::MigrationError(
+                format!("no migration from version {version}")
+            ))
+        };
+
+        (migration.migrate)(&mut table)?;
+
+        version += 1;
+        table.insert("version".to_string(), Value::Integer(version.into()));
+    }
+
+    match Value::Table(table).try_into::<MessageCacheData>() {
+        Ok(cache) => Ok(cache),
+        Err(_e) => Err(MessageCacheError::TomlParseError)
+    }
+}
+
+/// Version 2 stores nonces as raw bytes rather than alphanumeric
+/// strings, and tags each message with the id of its key. Every
+/// message before it was encrypted with the legacy key.
+fn add_key_ids(table: &mut Table) -> Result<(), MessageCacheError> {
+    for_each_message(table, |message| {
+        let nonce = match message.get("nonce") {
+            Some(Value::String(nonce)) => nonce.bytes()
+                .map(|byte| Value::Integer(byte.into()))
+                .collect(),
+            _ => return Err(MessageCacheError::MigrationError("message nonce is not a string".to_string()))
+        };
+
+        message.insert("nonce".to_string(), Value::Array(nonce));
+        message.insert("key_id".to_string(), Value::Integer(LEGACY_KEY_ID.into()));
+
+        Ok(())
+    })
+}
+
+/// Version 3 encrypts messages with per-user data keys. Every
+/// message before it was encrypted directly with the keyring.
+fn add_envelope_flags(table: &mut Table) -> Result<(), MessageCacheError> {
+    for_each_message(table, |message| {
+        message.insert("envelope".to_string(), Value::Boolean(false));
+
+        Ok(())
+    })
+}
+
+/// Version 4 records the guild each message was sent in. Older
+/// messages can't be matched to a guild from the file alone, so
+/// they are left without one until `UserMessageCache::assign_guild`
+/// sees their channel's guild.
+fn add_guild_ids(_table: &mut Table) -> Result<(), MessageCacheError> {
+    Ok(())
+}
+
+/// Runs `migrate` on every cached message table in a
+/// `<user_id, map<channel_id, Vec<msg>>>` data table
+fn for_each_message(
+    table: &mut Table,
+    mut migrate: impl FnMut(&mut Table) -> Result<(), MessageCacheError>
+) -> Result<(), MessageCacheError> {
+    let users = match table.get_mut("data") {
+        Some(Value::Table(users)) => users,
+        Some(_) => return Err(MessageCacheError::MigrationError("`data` is not a table".to_string())),
+        None => return Ok(())
+    };
+
+    for (_user_id, channels) in users.iter_mut() {
+        let channels = match channels {
+            Value::Table(channels) => channels,
+            _ => return Err(MessageCacheError::MigrationError("user entry is not a table".to_string()))
+        };
+
+        for (_channel_id, messages) in channels.iter_mut() {
+            let messages = match messages {
+                Value::Array(messages) => messages,
+                _ => return Err(MessageCacheError::MigrationError("channel entry is not an array".to_string()))
+            };
+
+            for message in messages.iter_mut() {
+                match message {
+                    Value::Table(message) => migrate(message)?,
+                    _ => return Err(MessageCacheError::MigrationError("message is not a table".to_string()))
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::CacheMessage;
+
+    const V1_CACHE: &str = r#"
+        version = 1
+
+        [[data.111.222]]
+        id = "333"
+        channel_id = "222"
+        time = 1700000000
+        data = [1, 2, 3]
+        nonce = "abc"
+    "#;
+
+    fn get_message(cache: &MessageCacheData) -> &CacheMessage {
+        &cache.data["111"]["222"][0]
+    }
+
+    #[test]
+    fn v1_migrates_to_current() {
+        let cache = read_cache(V1_CACHE).expect("Expected v1 cache to migrate");
+        let message = get_message(&cache);
+
+        assert_eq!(cache.version, CURRENT_CACHE_VERSION);
+        assert_eq!(message.id, "333");
+        assert_eq!(message.time, 1700000000);
+        assert_eq!(message.data, vec![1, 2, 3]);
+        assert_eq!(message.nonce, b"abc".to_vec());
+        assert_eq!(message.key_id, LEGACY_KEY_ID);
+        assert!(!message.envelope);
+        assert_eq!(message.guild_id, None);
+    }
+
+    #[test]
+    fn missing_version_is_v1() {
+        let contents = V1_CACHE.replace("version = 1", "");
+        let cache = read_cache(&contents).expect("Expected unversioned cache to migrate");
+
+        assert_eq!(get_message(&cache).nonce, b"abc".to_vec());
+        assert_eq!(get_message(&cache).key_id, LEGACY_KEY_ID);
+    }
+
+    #[test]
+    fn v2_gets_envelope_flags() {
+        let contents = r#"
+            version = 2
+
+            [[data.111.222]]
+            id = "333"
+            channel_id = "222"
+            time = 1
+            data = [1]
+            nonce = [9, 8]
+            key_id = 5
+        "#;
+
+        let cache = read_cache(contents).expect("Expected v2 cache to migrate");
+        let message = get_message(&cache);
+
+        assert_eq!(message.nonce, vec![9, 8]);
+        assert_eq!(message.key_id, 5);
+        assert!(!message.envelope);
+    }
+
+    #[test]
+    fn v3_keeps_messages_without_guilds() {
+        let contents = r#"
+            version = 3
+
+            [[data.111.222]]
+            id = "333"
+            channel_id = "222"
+            time = 1
+            data = [1]
+            nonce = [9]
+            key_id = 5
+            envelope = true
+        "#;
+
+        let cache = read_cache(contents).expect("Expected v3 cache to migrate");
+        let message = get_message(&cache);
+
+        assert_eq!(cache.version, CURRENT_CACHE_VERSION);
+        assert!(message.envelope);
+        assert_eq!(message.guild_id, None);
+    }
+
+    #[test]
+    fn current_version_is_read_as_is() {
+        let contents = r#"
+            version = 4
+
+            [[data.111.222]]
+            id = "333"
+            channel_id = "222"
+            guild_id = "444"
+            time = 1
+            data = [1]
+            nonce = [9]
+            key_id = 5
+            envelope = true
+        "#;
+
+        let cache = read_cache(contents).expect("Expected current cache to load");
+
+        assert_eq!(get_message(&cache).guild_id.as_deref(), Some("444"));
+    }
+
+    #[test]
+    fn empty_cache_migrates() {
+        let cache = read_cache("version = 1\n[data]\n").expect("Expected empty cache to migrate");
+
+        assert_eq!(cache.version, CURRENT_CACHE_VERSION);
+        assert!(cache.data.is_empty());
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let contents = format!("version = {}\n[data]\n", CURRENT_CACHE_VERSION + 1);
+
+        assert!(matches!(read_cache(&contents), Err(MessageCacheError::UnsupportedVersion(version)) if version == CURRENT_CACHE_VERSION + 1));
+    }
+
+    #[test]
+    fn malformed_v1_nonce_is_an_error() {
+        let contents = V1_CACHE.replace("nonce = \"abc\"", "nonce = 5");
+
+        assert!(matches!(read_cache(&contents), Err(MessageCacheError::MigrationError(_))));
+    }
+
+    #[test]
+    fn invalid_toml_is_an_error() {
+        assert!(matches!(read_cache("version = "), Err(MessageCacheError::TomlParseError)));
+    }
+}
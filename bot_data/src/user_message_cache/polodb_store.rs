@@ -0,0 +1,290 @@
+use polodb_core::{bson::{doc, Document}, Collection, Database, IndexModel};
+use serde::{Serialize, Deserialize};
+
+use super::{CacheMessage, MessageCacheData, MessageCacheError, MessageStore, TomlFileStore};
+
+/// Name of the collection holding cached messages
+const MESSAGES_COLLECTION: &str = "messages";
+
+/// A single cached message as it is stored in
+/// the database, alongside the ids it is indexed by
+#[derive(Clone, Serialize, Deserialize)]
+struct MessageDocument {
+    user_id: String,
+    channel_id: String,
+    message_id: String,
+
+    /// Documents written before guilds were recorded have none
+    #[serde(default)]
+    guild_id: Option<String>,
+
+    time: i64,
+    data: Vec<u8>,
+    nonce: StoredNonce,
+
+    /// Documents written before the keyring existed have no
+    /// key id, and were encrypted with the legacy key
+    #[serde(default)]
+    key_id: u32,
+
+    #[serde(default)]
+    envelope: bool,
+}
+
+/// Nonces used to be stored as alphanumeric strings,
+/// and are now stored as raw bytes
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredNonce {
+    Bytes(Vec<u8>),
+    Legacy(String)
+}
+
+impl StoredNonce {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Bytes(bytes) => bytes,
+            Self::Legacy(nonce) => nonce.into_bytes()
+        }
+    }
+}
+
+/// Converts bytes to the integer array serde stores `Vec<u8>` as
+fn bytes_to_array(bytes: &[u8]) -> Vec<i32> {
+    bytes.iter().map(|byte| *byte as i32).collect()
+}
+
+impl MessageDocument {
+    fn new(user_id: &str, message: &CacheMessage) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            channel_id: message.channel_id.clone(),
+            message_id: message.id.clone(),
+            guild_id: message.guild_id.clone(),
+            time: message.time,
+            data: message.data.clone(),
+            nonce: StoredNonce::Bytes(message.nonce.clone()),
+            key_id: message.key_id,
+            envelope: message.envelope
+        }
+    }
+
+    fn into_cache_message(self) -> CacheMessage {
+        CacheMessage {
+            id: self.message_id,
+            channel_id: self.channel_id,
+            guild_id: self.guild_id,
+            time: self.time,
+            data: self.data,
+            nonce: self.nonce.into_bytes(),
+            key_id: self.key_id,
+            envelope: self.envelope
+        }
+    }
+}
+
+/// Message storage backed by an embedded PoloDB database,
+/// where every cached message is its own document
+pub struct PoloDbStore {
+    db: Database,
+
+    /// TOML cache file imported if the database starts empty
+    toml_path: String,
+}
+
+impl PoloDbStore {
+    /// Opens (or creates) the database at `path` and makes
+    /// sure the user, channel, guild and message id indexes exist.
+    /// `toml_path` is imported on load if the database is empty
+    pub fn open(path: &str, toml_path: &str) -> Result<Self, MessageCacheError> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            if std::fs::create_dir_all(parent).is_err() {
+                return Err(MessageCacheError::PathCreateError);
+            }
+        }
+
+        let db = match Database::open_file(path) {
+            Ok(db) => db,
+            Err(e) => return Err(MessageCacheError::DatabaseError(e.to_string()))
+        };
+
+        let store = Self {
+            db,
+            toml_path: toml_path.to_string()
+        };
+
+        for key in ["user_id", "channel_id", "guild_id", "message_id"] {
+            let index = IndexModel {
+                keys: doc! { key: 1 },
+                options: None
+            };
+
+            if let Err(e) = store.messages().create_index(index) {
+                return Err(MessageCacheError::DatabaseError(e.to_string()));
+            }
+        }
+
+        Ok(store)
+    }
+
+    fn messages(&self) -> Collection<MessageDocument> {
+        self.db.collection::<MessageDocument>(MESSAGES_COLLECTION)
+    }
+
+    fn find_messages(&self, filter: Document) -> Result<Vec<MessageDocument>, MessageCacheError> {
+        let cursor = match self.messages().find(filter) {
+            Ok(cursor) => cursor,
+            Err(e) => return Err(MessageCacheError::DatabaseError(e.to_string()))
+        };
+
+        let mut documents = Vec::new();
+
+        for document in cursor {
+            match document {
+                Ok(document) => documents.push(document),
+                Err(e) => return Err(MessageCacheError::DatabaseError(e.to_string()))
+            }
+        }
+
+        Ok(documents)
+    }
+
+    /// Inserts every message in `cache` as its own document
+    pub fn import(&self, cache: &MessageCacheData) -> Result<(), MessageCacheError> {
+        let mut documents = Vec::new();
+
+        for (user_id, channels) in &cache.data {
+            for messages in channels.values() {
+                for message in messages {
+                    documents.push(MessageDocument::new(user_id, message));
+                }
+            }
+        }
+
+        if documents.is_empty() {
+            return Ok(());
+        }
+
+        match self.messages().insert_many(documents) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(MessageCacheError::DatabaseError(e.to_string()))
+        }
+    }
+
+    /// Returns `true` if the database holds no messages
+    pub fn is_empty(&self) -> Result<bool, MessageCacheError> {
+        match self.messages().count_documents() {
+            Ok(count) => Ok(count == 0),
+            Err(e) => Err(MessageCacheError::DatabaseError(e.to_string()))
+        }
+    }
+}
+
+impl MessageStore for PoloDbStore {
+    fn add_message(&mut self, user_id: u64, message: CacheMessage) -> Result<(), MessageCacheError> {
+        match self.messages().insert_one(MessageDocument::new(&user_id.to_string(), &message)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(MessageCacheError::DatabaseError(e.to_string()))
+        }
+    }
+
+    fn update_message(&mut self, user_id: u64, message: CacheMessage) -> Result<bool, MessageCacheError> {
+        let update = self.messages().update_one(
+            doc! {
+                "user_id": user_id.to_string(),
+                "message_id": message.id.clone()
+            },
+            doc! {
+                "$set": {
+                    "guild_id": message.guild_id.clone(),
+                    "time": message.time,
+                    "data": bytes_to_array(&message.data),
+                    "nonce": bytes_to_array(&message.nonce),
+                    "key_id": message.key_id,
+                    "envelope": message.envelope
+                }
+            }
+        );
+
+        match update {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(MessageCacheError::DatabaseError(e.to_string()))
+        }
+    }
+
+    fn remove_message(&mut self, user_id: u64, channel_id: u64, message_id: u64) -> Result<(), MessageCacheError> {
+        let delete = self.messages().delete_one(doc! {
+            "user_id": user_id.to_string(),
+            "channel_id": channel_id.to_string(),
+            "message_id": message_id.to_string()
+        });
+
+        match delete {
+            Ok(_) => Ok(()),
+            Err(e) => Err(MessageCacheError::DatabaseError(e.to_string()))
+        }
+    }
+
+    fn remove_messages_in_channel(&mut self, channel_id: u64) -> Result<(), MessageCacheError> {
+        match self.messages().delete_many(doc! { "channel_id": channel_id.to_string() }) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(MessageCacheError::DatabaseError(e.to_string()))
+        }
+    }
+
+    fn remove_guild_messages(&mut self, guild_id: u64) -> Result<(), MessageCacheError> {
+        match self.messages().delete_many(doc! { "guild_id": guild_id.to_string() }) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(MessageCacheError::DatabaseError(e.to_string()))
+        }
+    }
+
+    fn remove_user_messages(&mut self, user_id: u64) -> Result<(), MessageCacheError> {
+        match self.messages().delete_many(doc! { "user_id": user_id.to_string() }) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(MessageCacheError::DatabaseError(e.to_string()))
+        }
+    }
+
+    fn get_user_messages(&self, user_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError> {
+        let documents = self.find_messages(doc! { "user_id": user_id.to_string() })?;
+
+        Ok(documents.into_iter().map(MessageDocument::into_cache_message).collect())
+    }
+
+    fn get_user_guild_messages(&self, user_id: u64, guild_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError> {
+        let documents = self.find_messages(doc! {
+            "user_id": user_id.to_string(),
+            "guild_id": guild_id.to_string()
+        })?;
+
+        Ok(documents.into_iter().map(MessageDocument::into_cache_message).collect())
+    }
+
+    fn get_channel_messages(&self, channel_id: u64) -> Result<Vec<(u64, CacheMessage)>, MessageCacheError> {
+        let documents = self.find_messages(doc! { "channel_id": channel_id.to_string() })?;
+
+        Ok(documents.into_iter()
+            .map(|document| (document.user_id.parse().unwrap_or_default(), document.into_cache_message()))
+            .collect())
+    }
+
+    fn get_all_messages(&self) -> Result<Vec<(u64, CacheMessage)>, MessageCacheError> {
+        let documents = self.find_messages(doc! {})?;
+
+        Ok(documents.into_iter()
+            .map(|document| (document.user_id.parse().unwrap_or_default(), document.into_cache_message()))
+            .collect())
+    }
+
+    /// Every change is already written as it happens, so loading
+    /// only seeds an empty database from the TOML cache file
+    /// if one is still around
+    fn load(&mut self) -> Result<(), MessageCacheError> {
+        if self.is_empty()? && std::path::Path::new(&self.toml_path).exists() {
+            self.import(&TomlFileStore::read_file(&self.toml_path)?)?;
+        }
+
+        Ok(())
+    }
+}
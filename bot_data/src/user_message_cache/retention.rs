@@ -0,0 +1,427 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use super::CacheMessage;
+
+/// Default number of messages kept per user, across all channels
+pub const DEFAULT_MAX_USER_MESSAGES: usize = 200;
+
+/// Limits on what the message cache keeps around.
+///
+/// Whenever a limit is exceeded, the oldest messages
+/// are evicted first until every limit is met again.
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+    /// Messages kept per user, across all channels and guilds
+    pub max_user_messages: usize,
+
+    /// Messages kept per guild, if limited
+    pub max_guild_messages: Option<usize>,
+
+    /// How long messages are kept, if limited
+    pub max_age: Option<Duration>,
+
+    /// Total bytes of encrypted message data kept, if limited
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_user_messages: DEFAULT_MAX_USER_MESSAGES,
+            max_guild_messages: None,
+            max_age: None,
+            max_bytes: None
+        }
+    }
+}
+
+/// Identifies a single cached message
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageKey {
+    pub user_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+}
+
+impl MessageKey {
+    /// Returns the key of `message`, sent by `user_id`
+    pub fn new(user_id: u64, message: &CacheMessage) -> Self {
+        Self {
+            user_id,
+            channel_id: message.channel_id.parse().unwrap_or_default(),
+            message_id: message.id.parse().unwrap_or_default()
+        }
+    }
+}
+
+/// What the index needs to know about a message to apply a policy
+#[derive(Clone, Copy, Debug)]
+struct IndexEntry {
+    time: i64,
+    guild_id: Option<u64>,
+    size: usize,
+}
+
+/// Time-ordered index of every cached message, kept alongside
+/// the message store so retention can find the oldest messages
+/// without reading every message back out of the store
+#[derive(Default)]
+pub struct RetentionIndex {
+    entries: HashMap<MessageKey, IndexEntry>,
+
+    // (time, key), oldest first
+    by_time: BTreeSet<(i64, MessageKey)>,
+
+    user_counts: HashMap<u64, usize>,
+    guild_counts: HashMap<u64, usize>,
+    total_bytes: usize,
+}
+
+impl RetentionIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds an index over `messages`, each paired with its author
+    pub fn from_messages(messages: impl IntoIterator<Item = (u64, CacheMessage)>) -> Self {
+        let mut index = Self::new();
+
+        for (user_id, message) in messages {
+            index.insert(user_id, &message);
+        }
+
+        index
+    }
+
+    /// Returns the number of indexed messages
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    /// Returns `true` if no messages are indexed
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Returns the total bytes of indexed message data
+    pub fn get_total_bytes(&self) -> usize { self.total_bytes }
+
+    /// Returns the number of messages indexed for `user_id`
+    pub fn get_user_count(&self, user_id: u64) -> usize {
+        self.user_counts.get(&user_id).copied().unwrap_or_default()
+    }
+
+    /// Returns the number of messages indexed for `guild_id`
+    pub fn get_guild_count(&self, guild_id: u64) -> usize {
+        self.guild_counts.get(&guild_id).copied().unwrap_or_default()
+    }
+
+    /// Indexes `message`, sent by `user_id`, replacing
+    /// any entry already indexed for it
+    pub fn insert(&mut self, user_id: u64, message: &CacheMessage) {
+        let key = MessageKey::new(user_id, message);
+
+        self.remove(&key);
+
+        let entry = IndexEntry {
+            time: message.time,
+            guild_id: message.guild_id.as_ref().and_then(|id| id.parse().ok()),
+            size: message.data.len() + message.nonce.len()
+        };
+
+        *self.user_counts.entry(key.user_id).or_default() += 1;
+
+        if let Some(guild_id) = entry.guild_id {
+            *self.guild_counts.entry(guild_id).or_default() += 1;
+        }
+
+        self.total_bytes += entry.size;
+        self.by_time.insert((entry.time, key));
+        self.entries.insert(key, entry);
+    }
+
+    /// Removes a message from the index. Returns `false`
+    /// if it wasn't indexed.
+    pub fn remove(&mut self, key: &MessageKey) -> bool {
+        let entry = match self.entries.remove(key) {
+            Some(entry) => entry,
+            None => return false
+        };
+
+        self.by_time.remove(&(entry.time, *key));
+        self.total_bytes -= entry.size;
+
+        decrement(&mut self.user_counts, key.user_id);
+
+        if let Some(guild_id) = entry.guild_id {
+            decrement(&mut self.guild_counts, guild_id);
+        }
+
+        true
+    }
+
+    /// Removes every message in `channel_id` from the index
+    pub fn remove_channel(&mut self, channel_id: u64) {
+        self.remove_where(|key, _| key.channel_id == channel_id);
+    }
+
+    /// Removes every message sent by `user_id` from the index
+    pub fn remove_user(&mut self, user_id: u64) {
+        self.remove_where(|key, _| key.user_id == user_id);
+    }
+
+    /// Removes every message sent in `guild_id` from the index
+    pub fn remove_guild(&mut self, guild_id: u64) {
+        self.remove_where(|_, entry| entry.guild_id == Some(guild_id));
+    }
+
+    fn remove_where(&mut self, mut predicate: impl FnMut(&MessageKey, &IndexEntry) -> bool) {
+        let keys = self.entries.iter()
+            .filter(|(key, entry)| predicate(key, entry))
+            .map(|(key, _)| *key)
+            .collect::<Vec<MessageKey>>();
+
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    /// Removes the messages `policy` no longer allows from the
+    /// index, oldest first, and returns their keys so they can be
+    /// removed from the store too. `now` is a unix timestamp in
+    /// seconds, like `CacheMessage::time`.
+    pub fn evict(&mut self, policy: &RetentionPolicy, now: i64) -> Vec<MessageKey> {
+        let cutoff = policy.max_age
+            .map(|max_age| now.saturating_sub(i64::try_from(max_age.as_secs()).unwrap_or(i64::MAX)));
+
+        let mut user_counts = self.user_counts.clone();
+        let mut guild_counts = self.guild_counts.clone();
+        let mut total_bytes = self.total_bytes;
+
+        let mut over_users = user_counts.values()
+            .filter(|count| **count > policy.max_user_messages)
+            .count();
+
+        let mut over_guilds = match policy.max_guild_messages {
+            Some(max) => guild_counts.values().filter(|count| **count > max).count(),
+            None => 0
+        };
+
+        let mut evicted = Vec::new();
+
+        for (time, key) in &self.by_time {
+            let over_bytes = policy.max_bytes.is_some_and(|max| total_bytes > max);
+            let expired = cutoff.is_some_and(|cutoff| *time < cutoff);
+
+            // everything left is new enough and within every cap
+            if !expired && !over_bytes && over_users == 0 && over_guilds == 0 {
+                break;
+            }
+
+            let entry = &self.entries[key];
+
+            let user_count = user_counts.get(&key.user_id).copied().unwrap_or_default();
+            let user_over = user_count > policy.max_user_messages;
+
+            let guild_over = match (entry.guild_id, policy.max_guild_messages) {
+                (Some(guild_id), Some(max)) => guild_counts.get(&guild_id).copied().unwrap_or_default() > max,
+                _ => false
+            };
+
+            if !(expired || over_bytes || user_over || guild_over) {
+                continue;
+            }
+
+            if user_over && user_count - 1 <= policy.max_user_messages {
+                over_users -= 1;
+            }
+
+            decrement(&mut user_counts, key.user_id);
+
+            if let Some(guild_id) = entry.guild_id {
+                if guild_over && guild_counts[&guild_id] - 1 <= policy.max_guild_messages.unwrap_or(usize::MAX) {
+                    over_guilds -= 1;
+                }
+
+                decrement(&mut guild_counts, guild_id);
+            }
+
+            total_bytes -= entry.size;
+            evicted.push(*key);
+        }
+
+        for key in &evicted {
+            self.remove(key);
+        }
+
+        evicted
+    }
+}
+
+fn decrement(counts: &mut HashMap<u64, usize>, id: u64) {
+    if let Some(count) = counts.get_mut(&id) {
+        *count -= 1;
+
+        if *count == 0 {
+            counts.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: u64, channel_id: u64, guild_id: Option<u64>, time: i64, size: usize) -> CacheMessage {
+        CacheMessage {
+            id: id.to_string(),
+            channel_id: channel_id.to_string(),
+            guild_id: guild_id.map(|id| id.to_string()),
+            time,
+            data: vec![0; size],
+            nonce: Vec::new(),
+            key_id: 1,
+            envelope: true
+        }
+    }
+
+    fn ids(keys: &[MessageKey]) -> Vec<u64> {
+        keys.iter().map(|key| key.message_id).collect()
+    }
+
+    fn policy() -> RetentionPolicy {
+        RetentionPolicy {
+            max_user_messages: usize::MAX,
+            ..RetentionPolicy::default()
+        }
+    }
+
+    #[test]
+    fn nothing_evicted_within_limits() {
+        let mut index = RetentionIndex::from_messages([
+            (1, message(1, 10, Some(100), 1, 4)),
+            (1, message(2, 10, Some(100), 2, 4))
+        ]);
+
+        assert!(index.evict(&RetentionPolicy::default(), 10).is_empty());
+        assert_eq!(index.len(), 2);
+    }
+
+    #[test]
+    fn user_cap_applies_across_channels() {
+        let mut index = RetentionIndex::from_messages([
+            (1, message(1, 10, Some(100), 3, 1)),
+            (1, message(2, 11, Some(100), 1, 1)),
+            (1, message(3, 12, Some(200), 2, 1)),
+            (2, message(4, 10, Some(100), 0, 1))
+        ]);
+
+        let evicted = index.evict(&RetentionPolicy { max_user_messages: 1, ..policy() }, 10);
+
+        // user 2 is within the cap even though their message is the oldest
+        assert_eq!(ids(&evicted), vec![2, 3]);
+        assert_eq!(index.get_user_count(1), 1);
+        assert_eq!(index.get_user_count(2), 1);
+    }
+
+    #[test]
+    fn user_cap_of_zero_evicts_everything() {
+        let mut index = RetentionIndex::from_messages([
+            (1, message(1, 10, None, 1, 1)),
+            (2, message(2, 10, None, 2, 1))
+        ]);
+
+        assert_eq!(index.evict(&RetentionPolicy { max_user_messages: 0, ..policy() }, 10).len(), 2);
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn old_messages_expire() {
+        let mut index = RetentionIndex::from_messages([
+            (1, message(1, 10, None, 100, 1)),
+            (1, message(2, 10, None, 150, 1)),
+            (2, message(3, 10, None, 190, 1))
+        ]);
+
+        let evicted = index.evict(&RetentionPolicy { max_age: Some(Duration::from_secs(50)), ..policy() }, 200);
+
+        // a message exactly `max_age` old is kept
+        assert_eq!(ids(&evicted), vec![1]);
+        assert_eq!(index.len(), 2);
+    }
+
+    #[test]
+    fn guild_cap_ignores_other_guilds() {
+        let mut index = RetentionIndex::from_messages([
+            (1, message(1, 10, Some(100), 1, 1)),
+            (2, message(2, 20, Some(200), 2, 1)),
+            (3, message(3, 10, Some(100), 3, 1)),
+            (4, message(4, 30, None, 0, 1))
+        ]);
+
+        let evicted = index.evict(&RetentionPolicy { max_guild_messages: Some(1), ..policy() }, 10);
+
+        assert_eq!(ids(&evicted), vec![1]);
+        assert_eq!(index.get_guild_count(100), 1);
+        assert_eq!(index.get_guild_count(200), 1);
+    }
+
+    #[test]
+    fn memory_budget_evicts_oldest_first() {
+        let mut index = RetentionIndex::from_messages([
+            (1, message(1, 10, None, 3, 10)),
+            (2, message(2, 10, None, 1, 10)),
+            (3, message(3, 10, None, 2, 10))
+        ]);
+
+        let evicted = index.evict(&RetentionPolicy { max_bytes: Some(15), ..policy() }, 10);
+
+        assert_eq!(ids(&evicted), vec![2, 3]);
+        assert_eq!(index.get_total_bytes(), 10);
+    }
+
+    #[test]
+    fn messages_with_the_same_time_are_all_indexed() {
+        let mut index = RetentionIndex::from_messages([
+            (1, message(1, 10, None, 5, 1)),
+            (1, message(2, 10, None, 5, 1)),
+            (1, message(3, 10, None, 5, 1))
+        ]);
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.evict(&RetentionPolicy { max_user_messages: 1, ..policy() }, 10).len(), 2);
+        assert_eq!(index.get_user_count(1), 1);
+    }
+
+    #[test]
+    fn updating_a_message_replaces_its_entry() {
+        let mut index = RetentionIndex::new();
+
+        index.insert(1, &message(1, 10, Some(100), 1, 10));
+        index.insert(1, &message(1, 10, Some(100), 5, 4));
+
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.get_total_bytes(), 4);
+        assert_eq!(index.get_guild_count(100), 1);
+
+        // the old timestamp no longer counts towards expiry
+        assert!(index.evict(&RetentionPolicy { max_age: Some(Duration::from_secs(5)), ..policy() }, 8).is_empty());
+    }
+
+    #[test]
+    fn removals_keep_counts_in_sync() {
+        let mut index = RetentionIndex::from_messages([
+            (1, message(1, 10, Some(100), 1, 2)),
+            (1, message(2, 11, Some(100), 2, 2)),
+            (2, message(3, 10, Some(200), 3, 2))
+        ]);
+
+        index.remove_channel(10);
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.get_guild_count(200), 0);
+
+        index.remove_guild(100);
+        assert!(index.is_empty());
+        assert_eq!(index.get_total_bytes(), 0);
+        assert_eq!(index.get_user_count(1), 0);
+
+        assert!(!index.remove(&MessageKey { user_id: 1, channel_id: 10, message_id: 1 }));
+    }
+}
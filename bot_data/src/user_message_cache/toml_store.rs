@@ -0,0 +1,97 @@
+use super::{read_cache, CacheMessage, InMemoryStore, MessageCacheData, MessageCacheError, MessageStore};
+
+/// Message storage kept in memory and dumped to a
+/// TOML file whenever the cache is flushed
+pub struct TomlFileStore {
+    path: String,
+    messages: InMemoryStore
+}
+
+impl TomlFileStore {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            messages: InMemoryStore::new()
+        }
+    }
+
+    /// Reads the cache file at `path` without touching the
+    /// stored messages, migrating it from older versions
+    pub fn read_file(path: &str) -> Result<MessageCacheData, MessageCacheError> {
+        if let Ok(contents) = std::fs::read_to_string(path) {
+            read_cache(contents.as_str())
+        } else {
+            Err(MessageCacheError::TomlReadError)
+        }
+    }
+}
+
+impl MessageStore for TomlFileStore {
+    fn add_message(&mut self, user_id: u64, message: CacheMessage) -> Result<(), MessageCacheError> {
+        self.messages.add_message(user_id, message)
+    }
+
+    fn update_message(&mut self, user_id: u64, message: CacheMessage) -> Result<bool, MessageCacheError> {
+        self.messages.update_message(user_id, message)
+    }
+
+    fn remove_message(&mut self, user_id: u64, channel_id: u64, message_id: u64) -> Result<(), MessageCacheError> {
+        self.messages.remove_message(user_id, channel_id, message_id)
+    }
+
+    fn remove_messages_in_channel(&mut self, channel_id: u64) -> Result<(), MessageCacheError> {
+        self.messages.remove_messages_in_channel(channel_id)
+    }
+
+    fn remove_guild_messages(&mut self, guild_id: u64) -> Result<(), MessageCacheError> {
+        self.messages.remove_guild_messages(guild_id)
+    }
+
+    fn remove_user_messages(&mut self, user_id: u64) -> Result<(), MessageCacheError> {
+        self.messages.remove_user_messages(user_id)
+    }
+
+    fn get_user_messages(&self, user_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError> {
+        self.messages.get_user_messages(user_id)
+    }
+
+    fn get_user_guild_messages(&self, user_id: u64, guild_id: u64) -> Result<Vec<CacheMessage>, MessageCacheError> {
+        self.messages.get_user_guild_messages(user_id, guild_id)
+    }
+
+    fn get_channel_messages(&self, channel_id: u64) -> Result<Vec<(u64, CacheMessage)>, MessageCacheError> {
+        self.messages.get_channel_messages(channel_id)
+    }
+
+    fn get_all_messages(&self) -> Result<Vec<(u64, CacheMessage)>, MessageCacheError> {
+        self.messages.get_all_messages()
+    }
+
+    fn load(&mut self) -> Result<(), MessageCacheError> {
+        self.messages = InMemoryStore::from_data(Self::read_file(&self.path)?);
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), MessageCacheError> {
+        match toml::to_string(self.messages.data()) {
+            Ok(data) => {
+                if let Some(parent) = std::path::Path::new(&self.path).parent() {
+                    if let Err(e) = std::fs::create_dir_all(parent) {
+                        println!("{e:?}");
+                        return Err(MessageCacheError::PathCreateError);
+                    }
+                }
+
+                if let Err(_e) = std::fs::write(&self.path, data) {
+                    Err(MessageCacheError::FileWriteError)
+                } else {
+                    Ok(())
+                }
+            },
+            Err(_e) => {
+                Err(MessageCacheError::TomlConvertError)
+            }
+        }
+    }
+}
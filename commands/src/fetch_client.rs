@@ -0,0 +1,92 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bot_data::config::HttpConfig;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode};
+use serenity::prelude::TypeMapKey;
+
+use crate::fetch_error::FetchError;
+
+/// Longest wait between retries, whatever a server asks for
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(10);
+
+/// A pooled http client for outside APIs, which retries
+/// requests that fail with a 5xx or 429 response
+pub struct FetchClient {
+    client: Client,
+    max_retries: u32,
+    retry_backoff: Duration,
+}
+
+impl FetchClient {
+    pub fn from_config(config: &HttpConfig) -> Result<Self, FetchError> {
+        let client = Client::builder()
+            .timeout(config.get_timeout())
+            .connect_timeout(config.get_connect_timeout())
+            .user_agent(concat!("rittou/", env!("CARGO_PKG_VERSION")))
+            .build();
+
+        match client {
+            Ok(client) => Ok(Self {
+                client,
+                max_retries: config.get_max_retries(),
+                retry_backoff: config.get_retry_backoff()
+            }),
+            Err(e) => Err(FetchError::RequestError(e))
+        }
+    }
+
+    /// GETs `url` and returns the response body, retrying
+    /// with backoff while the server is failing or rate limiting
+    pub async fn get_text(&self, url: &str) -> Result<String, FetchError> {
+        let mut attempt = 0;
+
+        loop {
+            let response = match self.client.get(url).send().await {
+                Ok(response) => response,
+                Err(e) if e.is_timeout() => return Err(FetchError::Timeout(e)),
+                Err(e) => return Err(FetchError::RequestError(e))
+            };
+
+            let status = response.status();
+
+            if status.is_success() {
+                return match response.text().await {
+                    Ok(data) => Ok(data),
+                    Err(e) if e.is_timeout() => Err(FetchError::Timeout(e)),
+                    Err(e) => Err(FetchError::DecodeError(e))
+                };
+            }
+
+            let retryable = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+
+            if !retryable || attempt >= self.max_retries {
+                return Err(FetchError::StatusError(status));
+            }
+
+            // use the server's wait if it gives one, otherwise back off exponentially
+            let retry_after = response.headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+                .map(Duration::from_secs);
+
+            let backoff = 2u32.checked_pow(attempt)
+                .and_then(|factor| self.retry_backoff.checked_mul(factor))
+                .unwrap_or(MAX_RETRY_WAIT);
+
+            let wait = retry_after.unwrap_or(backoff);
+
+            tokio::time::sleep(wait.min(MAX_RETRY_WAIT)).await;
+
+            attempt += 1;
+        }
+    }
+}
+
+pub struct FetchClientData;
+
+impl TypeMapKey for FetchClientData {
+    type Value = Arc<FetchClient>;
+}
@@ -9,10 +9,37 @@ pub enum FetchError {
     /// fails
     #[error("Could not decode HTTP request: {0}")]
     DecodeError(reqwest::Error),
-    
 
-    /// Returned when cat cannot be converted
-    /// to a CatObject
-    #[error("Could not parse request to DogObject: {0}")]
-    ParseError(serde_json::Error)
+    /// Returned when a request takes too long
+    #[error("Request timed out: {0}")]
+    Timeout(reqwest::Error),
+
+    /// Returned when the server responds with an error,
+    /// after any retries
+    #[error("Server responded with {0}")]
+    StatusError(reqwest::StatusCode),
+
+    /// Returned when the response has nothing in it
+    #[error("Server sent an empty response")]
+    EmptyResponse,
+
+    /// Returned when the response parses, but isn't
+    /// what was expected
+    #[error("Server sent an unexpected response: {0}")]
+    MalformedResponse(String),
+
+    /// Returned when a response from the named
+    /// source isn't valid JSON, or not the expected shape
+    #[error("Could not parse response from {0}: {1}")]
+    ParseError(String, serde_json::Error),
+
+    /// Returned when the named source is being skipped
+    /// after failing, and there is nothing to fall back on
+    #[error("{0} is unavailable right now, try again later")]
+    Unavailable(String),
+
+    /// Returned when the named data hasn't been
+    /// fetched yet, e.g. just after starting
+    #[error("{0} haven't been fetched yet")]
+    NotFetched(String)
 }
\ No newline at end of file
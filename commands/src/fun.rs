@@ -1,28 +1,32 @@
-use serenity::{
-    client::Context,
-    framework::standard::{
-        CommandResult,
-        Args,
-        macros::{
-            command,
-            group
-        }
-    },
-    model::channel::Message
-};
-
-#[group]
-#[commands(emojify)]
-pub struct Fun;
-
-#[command]
-/// Convert message text into emojis
-async fn emojify(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let text = args.rest().to_string();
-    
-    msg.reply(&ctx, convert_text_to_emojis(text)).await?;
-
-    Ok(())
+use serenity::async_trait;
+
+use crate::hybrid::{ArgKind, ArgSpec, HybridArgs, HybridCommand, Invocation};
+use crate::response::SlashResponse;
+use crate::slash_command::SlashContext;
+
+pub struct Emojify;
+
+#[async_trait]
+impl HybridCommand for Emojify {
+    fn name(&self) -> &str { "emojify" }
+
+    fn description(&self) -> &str { "Convert message text into emojis" }
+
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec {
+            name: "text",
+            description: "The text to convert",
+            kind: ArgKind::Text,
+            required: true,
+            autocomplete: false
+        }]
+    }
+
+    async fn run(&self, _ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, args: &HybridArgs) -> SlashResponse {
+        let text = args.get_string("text").unwrap_or_default().to_string();
+
+        SlashResponse::new(convert_text_to_emojis(text))
+    }
 }
 
 pub fn convert_text_to_emojis(text: String) -> String {
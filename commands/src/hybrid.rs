@@ -0,0 +1,481 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serenity::all::{AutocompleteChoice, CommandInteraction, CommandOptionType, ComponentInteraction, ResolvedValue};
+use serenity::async_trait;
+use serenity::builder::{CreateCommand, CreateCommandOption};
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::user::User;
+use serenity::model::Permissions;
+use serenity::prelude::TypeMapKey;
+use serenity::utils::parse_user_mention;
+
+use bot_data::config::ConfigData;
+use bot_data::settings::SettingsData;
+use crate::CommandRegistryData;
+use crate::response::SlashResponse;
+use crate::slash_command::{SlashCommand, SlashContext};
+
+/// The bot's owners, who may use owner-only commands
+pub struct OwnerData;
+
+impl TypeMapKey for OwnerData {
+    type Value = Arc<HashSet<UserId>>;
+}
+
+/// The kinds of argument a hybrid command can take
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A single word
+    Word,
+
+    /// Everything left in the message. Must be the last argument
+    Text,
+
+    /// A user mention or id
+    User,
+
+    /// A whole number
+    Integer,
+}
+
+/// An argument a hybrid command takes, both as a slash
+/// command option and as a word in a prefix command
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+
+    /// Suggest values while the user types, as slash command options.
+    /// Only for word and text arguments
+    pub autocomplete: bool,
+}
+
+/// Who may use a hybrid command, and where
+#[derive(Default, Clone, Copy)]
+pub struct CommandChecks {
+    pub owners_only: bool,
+    pub guild_only: bool,
+    pub permissions: Permissions,
+}
+
+pub enum ArgValue {
+    Word(String),
+    User(User),
+    Integer(i64),
+}
+
+/// Parsed arguments, by name
+#[derive(Default)]
+pub struct HybridArgs {
+    values: HashMap<&'static str, ArgValue>
+}
+
+impl HybridArgs {
+    /// Returns the word or text argument `name`
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        match self.values.get(name) {
+            Some(ArgValue::Word(value)) => Some(value),
+            _ => None
+        }
+    }
+
+    /// Returns the user argument `name`
+    pub fn get_user(&self, name: &str) -> Option<&User> {
+        match self.values.get(name) {
+            Some(ArgValue::User(user)) => Some(user),
+            _ => None
+        }
+    }
+
+    /// Returns the integer argument `name`
+    pub fn get_integer(&self, name: &str) -> Option<i64> {
+        match self.values.get(name) {
+            Some(ArgValue::Integer(value)) => Some(*value),
+            _ => None
+        }
+    }
+}
+
+/// Who ran a hybrid command, and where
+pub struct Invocation<'a> {
+    pub author: &'a User,
+    pub guild_id: Option<GuildId>,
+
+    /// The author's permissions in the channel, if in a guild
+    pub permissions: Option<Permissions>,
+}
+
+/// A command usable both as a slash command and with a prefix,
+/// e.g. `/emojify text:hello` and `!emojify hello`
+#[async_trait]
+pub trait HybridCommand: Send + Sync {
+    /// Returns the name the command is used by
+    fn name(&self) -> &str;
+
+    /// Returns a short description, shown in discord's command list
+    fn description(&self) -> &str;
+
+    /// Returns the arguments the command takes, in order
+    fn args(&self) -> &'static [ArgSpec] { &[] }
+
+    /// Returns who may use the command, and where
+    fn checks(&self) -> CommandChecks { CommandChecks::default() }
+
+    /// Runs the command and returns the reply
+    async fn run(&self, ctx: &SlashContext<'_>, invocation: &Invocation<'_>, args: &HybridArgs) -> SlashResponse;
+
+    /// Returns suggestions for `value`, the argument `arg` as typed so far
+    async fn autocomplete(&self, _ctx: &SlashContext<'_>, _arg: &str, _value: &str) -> Vec<AutocompleteChoice> {
+        Vec::new()
+    }
+
+    /// Handles a button on one of the command's replies being pressed.
+    /// `data` is the button's custom id after `"<name>:"`.
+    async fn handle_component(&self, _ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, _data: &str) -> SlashResponse {
+        SlashResponse::new("This button no longer works").ephemeral(true)
+    }
+}
+
+/// Returns why `invocation` may not use `command`, if it may not
+async fn check(ctx: &SlashContext<'_>, command: &dyn HybridCommand, invocation: &Invocation<'_>) -> Option<String> {
+    let checks = command.checks();
+
+    if checks.owners_only && !ctx.get_data::<OwnerData>().await.contains(&invocation.author.id) {
+        return Some("Only the bot's owners can use this command".to_string());
+    }
+
+    if checks.guild_only && invocation.guild_id.is_none() {
+        return Some("This command can only be used in a server".to_string());
+    }
+
+    if !checks.permissions.is_empty() {
+        let permissions = invocation.permissions.unwrap_or_else(Permissions::empty);
+
+        if !permissions.contains(checks.permissions) {
+            return Some(format!("You need the {} permission(s) to use this command", checks.permissions));
+        }
+    }
+
+    None
+}
+
+#[async_trait]
+impl<T: HybridCommand> SlashCommand for T {
+    fn name(&self) -> &str { HybridCommand::name(self) }
+
+    fn register(&self) -> CreateCommand {
+        let checks = self.checks();
+
+        let mut command = CreateCommand::new(HybridCommand::name(self)).description(self.description());
+
+        if checks.guild_only {
+            command = command.dm_permission(false);
+        }
+
+        // hide owner-only commands from everyone but admins,
+        // they are still checked when run
+        if checks.owners_only {
+            command = command.default_member_permissions(Permissions::ADMINISTRATOR);
+        } else if !checks.permissions.is_empty() {
+            command = command.default_member_permissions(checks.permissions);
+        }
+
+        for arg in self.args() {
+            let kind = match arg.kind {
+                ArgKind::Word | ArgKind::Text => CommandOptionType::String,
+                ArgKind::User => CommandOptionType::User,
+                ArgKind::Integer => CommandOptionType::Integer
+            };
+
+            let option = CreateCommandOption::new(kind, arg.name, arg.description)
+                .required(arg.required)
+                .set_autocomplete(arg.autocomplete);
+
+            command = command.add_option(option);
+        }
+
+        command
+    }
+
+    async fn run(&self, ctx: &SlashContext<'_>, command: &CommandInteraction) -> SlashResponse {
+        let invocation = Invocation {
+            author: &command.user,
+            guild_id: command.guild_id,
+            permissions: command.member.as_ref().and_then(|member| member.permissions)
+        };
+
+        if let Some(reason) = check(ctx, self, &invocation).await {
+            return SlashResponse::new(reason).ephemeral(true);
+        }
+
+        let mut args = HybridArgs::default();
+
+        for option in command.data.options() {
+            let spec = match self.args().iter().find(|spec| spec.name == option.name) {
+                Some(spec) => spec,
+                None => continue
+            };
+
+            let value = match option.value {
+                ResolvedValue::String(value) => ArgValue::Word(value.to_string()),
+                ResolvedValue::User(user, _) => ArgValue::User(user.clone()),
+                ResolvedValue::Integer(value) => ArgValue::Integer(value),
+                _ => continue
+            };
+
+            args.values.insert(spec.name, value);
+        }
+
+        HybridCommand::run(self, ctx, &invocation, &args).await
+    }
+
+    async fn autocomplete(&self, ctx: &SlashContext<'_>, command: &CommandInteraction) -> Vec<AutocompleteChoice> {
+        let option = match command.data.autocomplete() {
+            Some(option) => option,
+            None => return Vec::new()
+        };
+
+        HybridCommand::autocomplete(self, ctx, option.name, option.value).await
+    }
+
+    async fn handle_component(&self, ctx: &SlashContext<'_>, component: &ComponentInteraction, data: &str) -> SlashResponse {
+        let invocation = Invocation {
+            author: &component.user,
+            guild_id: component.guild_id,
+            permissions: component.member.as_ref().and_then(|member| member.permissions)
+        };
+
+        // whoever presses the button may not be who ran the command
+        if let Some(reason) = check(ctx, self, &invocation).await {
+            return SlashResponse::new(reason).ephemeral(true);
+        }
+
+        HybridCommand::handle_component(self, ctx, &invocation, data).await
+    }
+}
+
+/// Runs the hybrid command a message invokes with a prefix or
+/// mention, replying with its result. Does nothing for other messages.
+pub async fn run_prefix_command(ctx: &Context, msg: &Message) {
+    let prefix = get_prefix(ctx, msg).await;
+
+    let (name, rest) = match split_invocation(&msg.content, ctx.cache.current_user().id, prefix.as_deref()) {
+        Some(invocation) => invocation,
+        None => return
+    };
+
+    let (config, registry) = {
+        let data_read = ctx.data.read().await;
+
+        (
+            data_read.get::<ConfigData>().expect("Expected ConfigData").clone(),
+            data_read.get::<CommandRegistryData>().expect("Expected CommandRegistryData").clone()
+        )
+    };
+
+    let command = match registry.get_hybrid_command(name) {
+        Some(command) => command,
+        None => return
+    };
+
+    let slash_ctx = SlashContext::new(ctx, config);
+
+    let invocation = Invocation {
+        author: &msg.author,
+        guild_id: msg.guild_id,
+        permissions: get_prefix_permissions(ctx, msg)
+    };
+
+    let response = match check(&slash_ctx, command, &invocation).await {
+        Some(reason) => SlashResponse::new(reason),
+        None => match parse_prefix_args(ctx, msg, command, rest).await {
+            Ok(args) => command.run(&slash_ctx, &invocation, &args).await,
+            Err(e) => SlashResponse::new(e)
+        }
+    };
+
+    let reply = response.get_reply().to_create_message().reference_message(msg);
+
+    if let Err(e) = msg.channel_id.send_message(&ctx.http, reply).await {
+        println!("Cannot reply to prefix command: {e}");
+        return;
+    }
+
+    for follow_up in response.get_follow_ups() {
+        if let Err(e) = msg.channel_id.send_message(&ctx.http, follow_up.to_create_message()).await {
+            println!("Cannot send prefix command follow-up: {e}");
+        }
+    }
+}
+
+/// Returns the longest of the prefixes the message's guild has set,
+/// or else of the ones in the current config, that starts the message
+pub async fn get_prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    let (config, settings_lock) = {
+        let data_read = ctx.data.read().await;
+
+        (
+            data_read.get::<ConfigData>().expect("Expected ConfigData").clone(),
+            data_read.get::<SettingsData>().expect("Expected SettingsData").clone()
+        )
+    };
+
+    let settings = settings_lock.read().await;
+    let content = msg.content.trim_start();
+
+    let prefixes = msg.guild_id
+        .and_then(|guild_id| settings.get_guild_prefixes(guild_id.get()))
+        .unwrap_or(config.get_prefixes());
+
+    // prefer the longest match, so `!!` wins over `!`
+    prefixes.iter()
+        .filter(|prefix| !prefix.is_empty() && content.starts_with(prefix.as_str()))
+        .max_by_key(|prefix| prefix.len())
+        .cloned()
+}
+
+/// Splits the command name, and the text after it, from the bot's
+/// mention or `prefix` at the start of `content`. Mentions are
+/// matched first and may be followed by spaces, as in the framework.
+fn split_invocation<'a>(content: &'a str, bot_id: UserId, prefix: Option<&str>) -> Option<(&'a str, &'a str)> {
+    let content = content.trim_start();
+
+    let mention = content.strip_prefix("<@")
+        .map(|rest| rest.strip_prefix('!').unwrap_or(rest))
+        .and_then(|rest| rest.strip_prefix(bot_id.to_string().as_str()))
+        .and_then(|rest| rest.strip_prefix('>'));
+
+    let after_prefix = match mention {
+        Some(rest) => rest.trim_start(),
+        None => content.strip_prefix(prefix?)?
+    };
+
+    let name_len = after_prefix.find(char::is_whitespace).unwrap_or(after_prefix.len());
+
+    match after_prefix.split_at(name_len) {
+        ("", _) => None,
+        split => Some(split)
+    }
+}
+
+/// Returns the author's permissions in the message's channel,
+/// if it is in a cached guild
+fn get_prefix_permissions(ctx: &Context, msg: &Message) -> Option<Permissions> {
+    let guild = msg.guild(&ctx.cache)?;
+    let member = msg.member.as_ref()?;
+
+    let channel = guild.channels.get(&msg.channel_id)
+        .or_else(|| guild.threads.iter().find(|thread| thread.id == msg.channel_id))?;
+
+    Some(guild.partial_member_permissions_in(channel, msg.author.id, member))
+}
+
+/// Parses `text` into the arguments `command` takes
+async fn parse_prefix_args(ctx: &Context, msg: &Message, command: &dyn HybridCommand, text: &str) -> Result<HybridArgs, String> {
+    let mut args = HybridArgs::default();
+    let mut rest = text.trim();
+
+    for spec in command.args() {
+        let raw = match spec.kind {
+            ArgKind::Text => std::mem::take(&mut rest),
+            _ => {
+                let (word, remaining) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                rest = remaining.trim_start();
+                word
+            }
+        };
+
+        if raw.is_empty() {
+            if spec.required {
+                return Err(format!("Missing `{}`. Usage: {}", spec.name, get_usage(command)));
+            }
+
+            continue;
+        }
+
+        let value = match spec.kind {
+            ArgKind::Word | ArgKind::Text => ArgValue::Word(raw.to_string()),
+            ArgKind::Integer => match raw.parse::<i64>() {
+                Ok(value) => ArgValue::Integer(value),
+                Err(_) => return Err(format!("`{}` must be a whole number", spec.name))
+            },
+            ArgKind::User => {
+                let user_id = match parse_user_mention(raw).or_else(|| raw.parse::<u64>().ok().filter(|id| *id != 0).map(UserId::new)) {
+                    Some(user_id) => user_id,
+                    None => return Err(format!("`{}` must be a user mention or id", spec.name))
+                };
+
+                let user = match msg.mentions.iter().find(|user| user.id == user_id) {
+                    Some(user) => user.clone(),
+                    None => match user_id.to_user(ctx).await {
+                        Ok(user) => user,
+                        Err(e) => return Err(format!("Could not find user {user_id}: {e}"))
+                    }
+                };
+
+                ArgValue::User(user)
+            }
+        };
+
+        args.values.insert(spec.name, value);
+    }
+
+    Ok(args)
+}
+
+/// Returns how to use `command` as a prefix command, e.g. `emojify <text>`
+fn get_usage(command: &dyn HybridCommand) -> String {
+    let mut usage = format!("`{}", command.name());
+
+    for spec in command.args() {
+        if spec.required {
+            usage.push_str(&format!(" <{}>", spec.name));
+        } else {
+            usage.push_str(&format!(" [{}]", spec.name));
+        }
+    }
+
+    usage.push('`');
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOT_ID: UserId = UserId::new(42);
+
+    #[test]
+    fn prefix_is_split_from_name() {
+        assert_eq!(split_invocation("!emojify hello", BOT_ID, Some("!")), Some(("emojify", " hello")));
+        assert_eq!(split_invocation("  !!ping", BOT_ID, Some("!!")), Some(("ping", "")));
+    }
+
+    #[test]
+    fn name_matching_prefix_is_kept() {
+        assert_eq!(split_invocation("pingping pong", BOT_ID, Some("ping")), Some(("ping", " pong")));
+        assert_eq!(split_invocation("emojifyemojify emojify", BOT_ID, Some("emojify")), Some(("emojify", " emojify")));
+    }
+
+    #[test]
+    fn mentions_are_prefixes() {
+        assert_eq!(split_invocation("<@42> ping", BOT_ID, None), Some(("ping", "")));
+        assert_eq!(split_invocation("<@!42>ping now", BOT_ID, Some("!")), Some(("ping", " now")));
+    }
+
+    #[test]
+    fn other_mentions_are_not_prefixes() {
+        assert_eq!(split_invocation("<@43> ping", BOT_ID, None), None);
+    }
+
+    #[test]
+    fn name_must_follow_prefix() {
+        assert_eq!(split_invocation("! ping", BOT_ID, Some("!")), None);
+        assert_eq!(split_invocation("!", BOT_ID, Some("!")), None);
+        assert_eq!(split_invocation("ping", BOT_ID, None), None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bot_data::config::{FallbackConfig, ImageProviderConfig};
+use rand::seq::IteratorRandom;
+use serenity::async_trait;
+use serenity::prelude::Mutex;
+
+use crate::fetch_client::FetchClient;
+use crate::fetch_error::FetchError;
+use crate::image_provider::{Breed, Image, ImageProvider};
+
+/// File extensions served from a fallback directory
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+/// Stops calling an upstream after too many failures in a row,
+/// until a cooldown has passed
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cooldown,
+            failures: 0,
+            open_until: None
+        }
+    }
+
+    /// Returns `true` if the upstream should be skipped for now
+    pub fn is_open(&self) -> bool {
+        self.open_until.is_some_and(|open_until| Instant::now() < open_until)
+    }
+
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.open_until = None;
+    }
+
+    /// Counts a failure, opening the breaker once there are enough.
+    /// Once a cooldown ends, a single failure opens it again.
+    pub fn record_failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+
+        if self.failures >= self.failure_threshold {
+            self.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// Images to serve while an upstream is down: the last few fetched,
+/// plus any urls and files given in config
+pub struct ImagePool {
+    max_size: usize,
+    fetched: VecDeque<Image>,
+    fixed: Vec<Image>,
+}
+
+impl ImagePool {
+    pub fn new(max_size: usize, fixed: Vec<Image>) -> Self {
+        Self {
+            max_size,
+            fetched: VecDeque::new(),
+            fixed
+        }
+    }
+
+    /// Builds a pool holding the fallback urls and files of `config`
+    pub fn from_config(config: &ImageProviderConfig, max_size: usize) -> Self {
+        let mut fixed = config.get_fallback_urls().iter()
+            .map(|url| Image {
+                url: url.clone(),
+                link: None,
+                attribution: config.get_attribution().map(str::to_string),
+                breed: None,
+                file: None
+            })
+            .collect::<Vec<Image>>();
+
+        if let Some(dir) = config.get_fallback_dir() {
+            match read_image_dir(Path::new(dir)) {
+                Ok(images) => fixed.extend(images),
+                Err(e) => println!("Cannot read fallback images for {} from {dir}: {e}", config.get_name())
+            }
+        }
+
+        Self::new(max_size, fixed)
+    }
+
+    /// Keeps `image`, dropping the oldest fetched image if full
+    pub fn add(&mut self, image: Image) {
+        if self.max_size == 0 || self.fetched.iter().any(|fetched| fetched.url == image.url) {
+            return;
+        }
+
+        if self.fetched.len() >= self.max_size {
+            self.fetched.pop_front();
+        }
+
+        self.fetched.push_back(image);
+    }
+
+    /// Returns a random image, of the breed named `breed` if given.
+    /// Only fetched images have a breed, so only they are picked from then.
+    pub fn pick(&self, breed: Option<&str>) -> Option<Image> {
+        let fetched = self.fetched.iter()
+            .filter(|image| breed.is_none() || image.breed.as_deref() == breed);
+
+        let fixed = self.fixed.iter().filter(|_| breed.is_none());
+
+        fetched.chain(fixed).choose(&mut rand::thread_rng()).cloned()
+    }
+}
+
+/// Wraps a provider, serving pooled images when it fails
+/// and skipping it for a while once it keeps failing
+pub struct FallbackImageProvider {
+    inner: Box<dyn ImageProvider>,
+    pool: Mutex<ImagePool>,
+    breaker: Mutex<CircuitBreaker>,
+}
+
+impl FallbackImageProvider {
+    pub fn new(inner: Box<dyn ImageProvider>, pool: ImagePool, breaker: CircuitBreaker) -> Self {
+        Self {
+            inner,
+            pool: Mutex::new(pool),
+            breaker: Mutex::new(breaker)
+        }
+    }
+
+    /// Wraps `inner` with the pool and breaker settings in config
+    pub fn from_config(inner: Box<dyn ImageProvider>, config: &ImageProviderConfig, fallback_config: &FallbackConfig) -> Self {
+        Self::new(
+            inner,
+            ImagePool::from_config(config, fallback_config.get_pool_size()),
+            CircuitBreaker::new(fallback_config.get_failure_threshold(), fallback_config.get_cooldown())
+        )
+    }
+
+    async fn pick(&self, breed: Option<&Breed>) -> Option<Image> {
+        self.pool.lock().await.pick(breed.map(|breed| breed.name.as_str()))
+    }
+}
+
+#[async_trait]
+impl ImageProvider for FallbackImageProvider {
+    fn name(&self) -> &str { self.inner.name() }
+
+    async fn fetch_image(&self, client: &FetchClient, breed: Option<&Breed>) -> Result<Image, FetchError> {
+        if self.breaker.lock().await.is_open() {
+            return match self.pick(breed).await {
+                Some(image) => Ok(image),
+                None => Err(FetchError::Unavailable(self.name().to_string()))
+            };
+        }
+
+        match self.inner.fetch_image(client, breed).await {
+            Ok(image) => {
+                self.breaker.lock().await.record_success();
+                self.pool.lock().await.add(image.clone());
+
+                Ok(image)
+            },
+            Err(e) => {
+                self.breaker.lock().await.record_failure();
+
+                match self.pick(breed).await {
+                    Some(image) => {
+                        println!("Cannot fetch {} image, serving a saved one: {e}", self.name());
+                        Ok(image)
+                    },
+                    None => Err(e)
+                }
+            }
+        }
+    }
+
+    fn has_breeds(&self) -> bool { self.inner.has_breeds() }
+
+    fn get_breed_refresh_interval(&self) -> Option<Duration> { self.inner.get_breed_refresh_interval() }
+
+    async fn get_breeds(&self) -> Result<Arc<Vec<Breed>>, FetchError> {
+        self.inner.get_breeds().await
+    }
+
+    async fn refresh_breeds(&self, client: &FetchClient) -> Result<(), FetchError> {
+        self.inner.refresh_breeds(client).await
+    }
+}
+
+/// Returns an image for each image file in `dir`
+fn read_image_dir(dir: &Path) -> std::io::Result<Vec<Image>> {
+    let mut images = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let is_image = path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+
+        let file_name = match path.file_name().and_then(|file_name| file_name.to_str()) {
+            Some(file_name) if is_image => file_name.to_string(),
+            _ => continue
+        };
+
+        images.push(Image {
+            url: format!("attachment://{file_name}"),
+            link: None,
+            attribution: None,
+            breed: None,
+            file: Some(path)
+        });
+    }
+
+    Ok(images)
+}
@@ -0,0 +1,251 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bot_data::config::{BreedListFormat, BreedsConfig, ImageProviderConfig};
+use serde_json::Value;
+use serenity::async_trait;
+use tokio::sync::RwLock;
+
+use crate::fetch_client::FetchClient;
+use crate::fetch_error::FetchError;
+
+/// A random image, with whatever credit its source asks for
+#[derive(Clone)]
+pub struct Image {
+    pub url: String,
+
+    /// A page about the image, if the source gives one
+    pub link: Option<String>,
+
+    pub attribution: Option<String>,
+
+    /// The breed's name, if one was asked for
+    pub breed: Option<String>,
+
+    /// A local file to attach, for images not hosted anywhere
+    pub file: Option<PathBuf>,
+}
+
+/// A breed images can be fetched for
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Breed {
+    /// What the API calls the breed, e.g. `hound/afghan`
+    pub id: String,
+
+    /// The name shown to users, e.g. `Afghan Hound`
+    pub name: String,
+}
+
+/// Somewhere random images can be fetched from
+#[async_trait]
+pub trait ImageProvider: Send + Sync {
+    /// Returns the provider's name, used in errors
+    fn name(&self) -> &str;
+
+    /// Fetches a random image, of `breed` if given
+    async fn fetch_image(&self, client: &FetchClient, breed: Option<&Breed>) -> Result<Image, FetchError>;
+
+    /// Returns `true` if images can be fetched by breed
+    fn has_breeds(&self) -> bool { false }
+
+    /// Returns how often the breed list should be fetched again
+    fn get_breed_refresh_interval(&self) -> Option<Duration> { None }
+
+    /// Returns every breed images can be fetched for, as last fetched.
+    /// Never waits on the network, so it is quick enough for autocomplete.
+    async fn get_breeds(&self) -> Result<Arc<Vec<Breed>>, FetchError> {
+        Ok(Arc::new(Vec::new()))
+    }
+
+    /// Fetches the breed list, keeping the old one if that fails
+    async fn refresh_breeds(&self, _client: &FetchClient) -> Result<(), FetchError> {
+        Ok(())
+    }
+}
+
+/// Fetches images from a JSON API, finding the
+/// image url with the pointers given in config
+pub struct JsonImageProvider {
+    config: ImageProviderConfig,
+
+    /// The breed list, once it has been fetched
+    breeds: RwLock<Option<Arc<Vec<Breed>>>>,
+}
+
+impl JsonImageProvider {
+    pub fn new(config: ImageProviderConfig) -> Self {
+        Self {
+            config,
+            breeds: RwLock::new(None)
+        }
+    }
+
+    pub fn get_config(&self) -> &ImageProviderConfig { &self.config }
+
+    /// Finds the image in a response body
+    pub fn parse_image(&self, data: &str) -> Result<Image, FetchError> {
+        if data.trim().is_empty() {
+            return Err(FetchError::EmptyResponse);
+        }
+
+        let value = match serde_json::from_str::<Value>(data) {
+            Ok(value) => value,
+            Err(e) => return Err(FetchError::ParseError(self.config.get_name().to_string(), e))
+        };
+
+        let url = match value.pointer(self.config.get_image_pointer()).and_then(Value::as_str) {
+            Some(url) => url,
+            None if is_empty(&value) => return Err(FetchError::EmptyResponse),
+            None => return Err(FetchError::MalformedResponse(format!("no image at `{}`", self.config.get_image_pointer())))
+        };
+
+        // error responses sometimes put their message where the image would be
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(FetchError::MalformedResponse(format!("`{url}` is not an image url")));
+        }
+
+        let link = self.config.get_link_pointer()
+            .and_then(|pointer| value.pointer(pointer))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        Ok(Image {
+            url: url.to_string(),
+            link,
+            attribution: self.config.get_attribution().map(str::to_string),
+            breed: None,
+            file: None
+        })
+    }
+
+    /// Finds the breeds in a breed list response, sorted by name
+    pub fn parse_breeds(&self, breeds_config: &BreedsConfig, data: &str) -> Result<Vec<Breed>, FetchError> {
+        if data.trim().is_empty() {
+            return Err(FetchError::EmptyResponse);
+        }
+
+        let value = match serde_json::from_str::<Value>(data) {
+            Ok(value) => value,
+            Err(e) => return Err(FetchError::ParseError(self.config.get_name().to_string(), e))
+        };
+
+        let list = match value.pointer(breeds_config.get_list_pointer()) {
+            Some(list) => list,
+            None => return Err(FetchError::MalformedResponse(format!("no breeds at `{}`", breeds_config.get_list_pointer())))
+        };
+
+        let mut breeds = match (breeds_config.get_format(), list) {
+            (BreedListFormat::List, Value::Array(entries)) => entries.iter()
+                .filter_map(|entry| {
+                    let id = match entry.pointer(breeds_config.get_id_pointer())? {
+                        Value::String(id) => id.clone(),
+                        Value::Number(id) => id.to_string(),
+                        _ => return None
+                    };
+
+                    let name = entry.pointer(breeds_config.get_name_pointer())
+                        .and_then(Value::as_str)
+                        .map_or_else(|| id.clone(), str::to_string);
+
+                    Some(Breed { id, name })
+                })
+                .collect::<Vec<_>>(),
+            (BreedListFormat::Map, Value::Object(entries)) => entries.iter()
+                .flat_map(|(breed, sub_breeds)| {
+                    let sub_breeds = sub_breeds.as_array().map(Vec::as_slice).unwrap_or_default();
+
+                    std::iter::once(Breed { id: breed.clone(), name: title_case(breed) })
+                        .chain(sub_breeds.iter().filter_map(Value::as_str).map(move |sub_breed| Breed {
+                            id: format!("{breed}/{sub_breed}"),
+                            name: title_case(&format!("{sub_breed} {breed}"))
+                        }))
+                })
+                .collect::<Vec<_>>(),
+            (format, _) => return Err(FetchError::MalformedResponse(format!("breeds are not a {format:?}")))
+        };
+
+        if breeds.is_empty() {
+            return Err(FetchError::EmptyResponse);
+        }
+
+        breeds.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(breeds)
+    }
+}
+
+#[async_trait]
+impl ImageProvider for JsonImageProvider {
+    fn name(&self) -> &str { self.config.get_name() }
+
+    async fn fetch_image(&self, client: &FetchClient, breed: Option<&Breed>) -> Result<Image, FetchError> {
+        let url = match (breed, self.config.get_breeds()) {
+            (Some(breed), Some(breeds_config)) => breeds_config.get_image_url(&breed.id),
+            _ => self.config.get_url().to_string()
+        };
+
+        let data = client.get_text(&url).await?;
+
+        let mut image = self.parse_image(&data)?;
+        image.breed = breed.map(|breed| breed.name.clone());
+
+        Ok(image)
+    }
+
+    fn has_breeds(&self) -> bool { self.config.get_breeds().is_some() }
+
+    fn get_breed_refresh_interval(&self) -> Option<Duration> {
+        self.config.get_breeds().map(BreedsConfig::get_refresh_interval)
+    }
+
+    async fn get_breeds(&self) -> Result<Arc<Vec<Breed>>, FetchError> {
+        if !self.has_breeds() {
+            return Ok(Arc::new(Vec::new()));
+        }
+
+        match &*self.breeds.read().await {
+            Some(breeds) => Ok(breeds.clone()),
+            None => Err(FetchError::NotFetched(format!("{} breeds", self.config.get_name())))
+        }
+    }
+
+    async fn refresh_breeds(&self, client: &FetchClient) -> Result<(), FetchError> {
+        let breeds_config = match self.config.get_breeds() {
+            Some(breeds_config) => breeds_config,
+            None => return Ok(())
+        };
+
+        // fetch before locking, so readers never wait on the network
+        let data = client.get_text(breeds_config.get_list_url()).await?;
+        let breeds = self.parse_breeds(breeds_config, &data)?;
+
+        *self.breeds.write().await = Some(Arc::new(breeds));
+
+        Ok(())
+    }
+}
+
+/// Capitalises each word, e.g. `afghan hound` to `Afghan Hound`
+fn title_case(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            let mut chars = word.chars();
+
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Array(values) => values.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => false
+    }
+}
@@ -1,6 +1,133 @@
 pub mod fetch_error;
-pub mod slash_cat;
-pub mod slash_dog;
+pub mod fetch_client;
+pub mod image_provider;
+pub mod image_pool;
+pub mod slash_command;
+pub mod response;
+pub mod hybrid;
+pub mod registry;
+pub mod slash_image;
 pub mod slash_scramblr;
+pub mod slash_privacy;
+pub mod slash_caching;
+pub mod slash_mydata;
 pub mod utility;
 pub mod fun;
+
+use std::sync::Arc;
+
+use bot_data::config::Config;
+use serenity::builder::CreateCommand;
+use serenity::prelude::TypeMapKey;
+
+use crate::fetch_client::FetchClient;
+use crate::hybrid::HybridCommand;
+use crate::image_pool::FallbackImageProvider;
+use crate::image_provider::JsonImageProvider;
+use crate::slash_command::SlashCommand;
+use crate::slash_image::ImageCommand;
+
+/// Lists every built-in command, so adding one only takes its definition
+/// and one line here. Hybrid commands are also registered as slash commands.
+macro_rules! commands {
+    (
+        slash: [$($slash:path),* $(,)?],
+        hybrid: [$($hybrid:path),* $(,)?] $(,)?
+    ) => {
+        /// Every built-in slash command, in registration order
+        pub static SLASH_COMMANDS: &[&dyn SlashCommand] = &[$(&$slash,)* $(&$hybrid,)*];
+
+        /// Every built-in command also usable with a prefix
+        pub static HYBRID_COMMANDS: &[&dyn HybridCommand] = &[$(&$hybrid),*];
+    };
+}
+
+commands! {
+    slash: [
+        slash_privacy::Privacy,
+        slash_caching::Caching,
+        slash_mydata::MyData,
+    ],
+    hybrid: [
+        slash_scramblr::Scramblr,
+        utility::Ping,
+        utility::Save,
+        utility::Load,
+        utility::Rotate,
+        utility::Forget,
+        utility::Reload,
+        utility::SyncCommands,
+        utility::Prefix,
+        fun::Emojify,
+    ],
+}
+
+/// The built-in commands plus those set up in config, like `/cat`
+pub struct CommandRegistry {
+    images: Vec<ImageCommand>,
+}
+
+impl CommandRegistry {
+    pub fn from_config(config: &Config) -> Self {
+        let mut images = Vec::new();
+
+        for provider in config.get_image_providers() {
+            if SLASH_COMMANDS.iter().any(|command| command.name() == provider.get_name()) {
+                println!("Image command `{}` has the same name as a built-in command, skipping", provider.get_name());
+                continue;
+            }
+
+            let fallback = FallbackImageProvider::from_config(
+                Box::new(JsonImageProvider::new(provider.clone())),
+                provider,
+                config.get_fallback_config()
+            );
+
+            images.push(ImageCommand::new(provider.get_description(), Box::new(fallback)));
+        }
+
+        Self { images }
+    }
+
+    /// Returns every slash command, ready to be registered
+    pub fn get_slash_commands(&self) -> Vec<CreateCommand> {
+        SLASH_COMMANDS.iter()
+            .map(|command| command.register())
+            .chain(self.images.iter().map(SlashCommand::register))
+            .collect()
+    }
+
+    /// Returns the slash command registered as `name`
+    pub fn get_slash_command(&self, name: &str) -> Option<&dyn SlashCommand> {
+        match SLASH_COMMANDS.iter().find(|command| command.name() == name) {
+            Some(command) => Some(*command),
+            None => self.images.iter()
+                .find(|command| SlashCommand::name(*command) == name)
+                .map(|command| command as &dyn SlashCommand)
+        }
+    }
+
+    /// Starts refreshing the breed lists of image commands that
+    /// have them, in the background until the registry is dropped
+    pub fn spawn_breed_refresh(&self, client: Arc<FetchClient>) {
+        for image in &self.images {
+            image.spawn_breed_refresh(client.clone());
+        }
+    }
+
+    /// Returns the hybrid command named `name`
+    pub fn get_hybrid_command(&self, name: &str) -> Option<&dyn HybridCommand> {
+        match HYBRID_COMMANDS.iter().find(|command| command.name() == name) {
+            Some(command) => Some(*command),
+            None => self.images.iter()
+                .find(|command| HybridCommand::name(*command) == name)
+                .map(|command| command as &dyn HybridCommand)
+        }
+    }
+}
+
+pub struct CommandRegistryData;
+
+impl TypeMapKey for CommandRegistryData {
+    type Value = Arc<CommandRegistry>;
+}
@@ -0,0 +1,416 @@
+use std::fmt;
+use std::sync::Arc;
+
+use bot_data::config::Config;
+use serde_json::{json, Value};
+use serenity::builder::CreateCommand;
+use serenity::http::Http;
+use serenity::model::application::Command;
+use serenity::model::id::{CommandId, GuildId};
+
+/// Where a set of slash commands is registered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandScope {
+    /// Every guild the bot is in, and DMs
+    Global,
+
+    /// A single guild, usually for testing
+    Guild(GuildId),
+}
+
+impl fmt::Display for CommandScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Global => write!(f, "global"),
+            Self::Guild(guild_id) => write!(f, "guild {guild_id}")
+        }
+    }
+}
+
+/// Returns the scopes commands should be registered in:
+/// the dev guild if set, and globally if enabled
+pub fn get_scopes(config: &Config) -> Vec<CommandScope> {
+    let mut scopes = Vec::new();
+
+    if let Some(id) = config.get_dev_guild_id() {
+        scopes.push(CommandScope::Guild(GuildId::new(*id)));
+    }
+
+    if config.get_commands_config().is_global() {
+        scopes.push(CommandScope::Global);
+    }
+
+    scopes
+}
+
+/// A single change needed to bring registered
+/// commands in line with the desired ones
+pub enum CommandChange {
+    Create(CreateCommand),
+    Update(CommandId, CreateCommand),
+    Delete(CommandId, String),
+}
+
+impl CommandChange {
+    /// Returns the name of the command being changed
+    pub fn get_name(&self) -> String {
+        match self {
+            Self::Create(command) | Self::Update(_, command) => get_builder_name(command),
+            Self::Delete(_, name) => name.clone()
+        }
+    }
+}
+
+impl fmt::Display for CommandChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Create(_) => write!(f, "create /{}", self.get_name()),
+            Self::Update(id, _) => write!(f, "update /{} ({id})", self.get_name()),
+            Self::Delete(id, _) => write!(f, "delete /{} ({id})", self.get_name())
+        }
+    }
+}
+
+/// Every change needed in one scope
+pub struct SyncPlan {
+    pub scope: CommandScope,
+    pub changes: Vec<CommandChange>,
+
+    /// Number of commands already registered as desired
+    pub unchanged: usize,
+}
+
+impl SyncPlan {
+    /// Returns `true` if nothing needs to change
+    pub fn is_empty(&self) -> bool { self.changes.is_empty() }
+}
+
+impl fmt::Display for SyncPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} commands: {} to change, {} unchanged", self.scope, self.changes.len(), self.unchanged)?;
+
+        for change in &self.changes {
+            write!(f, "\n  {change}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fetches the commands registered in `scope` and works out
+/// what to create, update or delete so they match `desired`
+pub async fn plan_sync(
+    http: &Arc<Http>,
+    scope: CommandScope,
+    desired: Vec<CreateCommand>
+) -> Result<SyncPlan, serenity::Error> {
+    let existing = match scope {
+        CommandScope::Global => Command::get_global_commands(http).await?,
+        CommandScope::Guild(guild_id) => guild_id.get_commands(http).await?
+    };
+
+    Ok(diff_commands(scope, existing, desired))
+}
+
+/// Works out what to create, update or delete so the
+/// `existing` commands in `scope` match `desired`
+fn diff_commands(scope: CommandScope, mut existing: Vec<Command>, desired: Vec<CreateCommand>) -> SyncPlan {
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+
+    for command in desired {
+        let desired_value = normalize_builder(&command, scope);
+
+        let position = existing.iter().position(|registered| {
+            registered.name == get_builder_name(&command) && registered.kind == get_builder_kind(&desired_value)
+        });
+
+        match position {
+            Some(position) => {
+                let registered = existing.swap_remove(position);
+
+                if normalize_command(&registered, scope) == desired_value {
+                    unchanged += 1;
+                } else {
+                    changes.push(CommandChange::Update(registered.id, command));
+                }
+            },
+            None => changes.push(CommandChange::Create(command))
+        }
+    }
+
+    // anything left over is no longer wanted
+    for registered in existing {
+        changes.push(CommandChange::Delete(registered.id, registered.name));
+    }
+
+    SyncPlan { scope, changes, unchanged }
+}
+
+/// Makes every change in `plan`, stopping at the first failure.
+/// Returns the number of changes made.
+pub async fn apply_plan(http: &Arc<Http>, plan: SyncPlan) -> Result<usize, serenity::Error> {
+    let mut applied = 0;
+
+    for change in plan.changes {
+        match (plan.scope, change) {
+            (CommandScope::Global, CommandChange::Create(command)) => {
+                Command::create_global_command(http, command).await?;
+            },
+            (CommandScope::Global, CommandChange::Update(id, command)) => {
+                Command::edit_global_command(http, id, command).await?;
+            },
+            (CommandScope::Global, CommandChange::Delete(id, _)) => {
+                Command::delete_global_command(http, id).await?;
+            },
+            (CommandScope::Guild(guild_id), CommandChange::Create(command)) => {
+                guild_id.create_command(http, command).await?;
+            },
+            (CommandScope::Guild(guild_id), CommandChange::Update(id, command)) => {
+                guild_id.edit_command(http, id, command).await?;
+            },
+            (CommandScope::Guild(guild_id), CommandChange::Delete(id, _)) => {
+                guild_id.delete_command(http, id).await?;
+            }
+        }
+
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+/// Plans the changes needed in `scope` and, unless `dry_run` is
+/// set, applies them. Returns the plan, for logging.
+pub async fn sync_commands(
+    http: &Arc<Http>,
+    scope: CommandScope,
+    desired: Vec<CreateCommand>,
+    dry_run: bool
+) -> Result<String, serenity::Error> {
+    let plan = plan_sync(http, scope, desired).await?;
+    let summary = plan.to_string();
+
+    if !dry_run && !plan.is_empty() {
+        apply_plan(http, plan).await?;
+    }
+
+    Ok(summary)
+}
+
+fn get_builder_name(command: &CreateCommand) -> String {
+    match serde_json::to_value(command) {
+        Ok(value) => value["name"].as_str().unwrap_or_default().to_string(),
+        Err(_e) => String::new()
+    }
+}
+
+fn get_builder_kind(value: &Value) -> serenity::model::application::CommandType {
+    serde_json::from_value(value["type"].clone()).unwrap_or(serenity::model::application::CommandType::ChatInput)
+}
+
+/// The parts of a command Discord keeps, in a form that can be
+/// compared between builders and registered commands
+fn normalize_builder(command: &CreateCommand, scope: CommandScope) -> Value {
+    let value = serde_json::to_value(command).unwrap_or_default();
+
+    normalize(
+        &value["name"],
+        &value["description"],
+        &value["options"],
+        &value["default_member_permissions"],
+        value["dm_permission"].as_bool(),
+        &value["nsfw"],
+        &value["type"],
+        scope
+    )
+}
+
+fn normalize_command(command: &Command, scope: CommandScope) -> Value {
+    normalize(
+        &json!(command.name),
+        &json!(command.description),
+        &serde_json::to_value(&command.options).unwrap_or_default(),
+        &json!(command.default_member_permissions.map(|permissions| permissions.bits().to_string())),
+        command.dm_permission,
+        &json!(command.nsfw),
+        &serde_json::to_value(command.kind).unwrap_or_default(),
+        scope
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn normalize(
+    name: &Value,
+    description: &Value,
+    options: &Value,
+    default_member_permissions: &Value,
+    dm_permission: Option<bool>,
+    nsfw: &Value,
+    kind: &Value,
+    scope: CommandScope
+) -> Value {
+    json!({
+        "name": name,
+        "description": description.as_str().unwrap_or_default(),
+        "options": normalize_options(options),
+        "default_member_permissions": default_member_permissions,
+        // only global commands can be used in DMs, and they are by default
+        "dm_permission": match scope {
+            CommandScope::Global => dm_permission.unwrap_or(true),
+            CommandScope::Guild(_) => true
+        },
+        "nsfw": nsfw.as_bool().unwrap_or_default(),
+        "type": if kind.is_null() { json!(1) } else { kind.clone() }
+    })
+}
+
+/// Drops localizations, which aren't returned unless asked for,
+/// and numbers' int/float distinction, which Discord doesn't keep
+fn normalize_options(options: &Value) -> Value {
+    let options = match options.as_array() {
+        Some(options) => options,
+        None => return json!([])
+    };
+
+    Value::Array(options.iter().map(|option| {
+        let mut option = option.clone();
+
+        if let Some(fields) = option.as_object_mut() {
+            fields.remove("name_localizations");
+            fields.remove("description_localizations");
+
+            for key in ["min_value", "max_value"] {
+                if let Some(number) = fields.get(key).and_then(Value::as_f64) {
+                    fields.insert(key.to_string(), json!(number));
+                }
+            }
+
+            if let Some(choices) = fields.get_mut("choices").and_then(Value::as_array_mut) {
+                for choice in choices {
+                    if let Some(choice) = choice.as_object_mut() {
+                        choice.remove("name_localizations");
+                    }
+                }
+            }
+
+            let sub_options = normalize_options(fields.get("options").unwrap_or(&Value::Null));
+            fields.insert("options".to_string(), sub_options);
+        }
+
+        option
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::builder::CreateCommandOption;
+    use serenity::model::application::CommandOptionType;
+
+    use super::*;
+
+    const SCOPE: CommandScope = CommandScope::Guild(GuildId::new(1));
+
+    /// A command as Discord returns it, with `fields` over the defaults
+    fn registered(id: u64, name: &str, fields: Value) -> Command {
+        let mut command = json!({
+            "id": id.to_string(),
+            "type": 1,
+            "application_id": "2",
+            "guild_id": "1",
+            "name": name,
+            "description": "Shows a number",
+            "options": [],
+            "default_member_permissions": null,
+            "version": "3"
+        });
+
+        if let (Some(command), Some(fields)) = (command.as_object_mut(), fields.as_object()) {
+            command.extend(fields.clone());
+        }
+
+        serde_json::from_value(command).unwrap()
+    }
+
+    fn builder() -> CreateCommand {
+        CreateCommand::new("number")
+            .description("Shows a number")
+            .add_option(CreateCommandOption::new(CommandOptionType::Integer, "max", "Highest number").min_int_value(1))
+    }
+
+    fn registered_options() -> Value {
+        json!({ "options": [{ "type": 4, "name": "max", "description": "Highest number", "min_value": 1 }] })
+    }
+
+    #[test]
+    fn unchanged_command_is_left_alone() {
+        let plan = diff_commands(SCOPE, vec![registered(10, "number", registered_options())], vec![builder()]);
+
+        assert!(plan.is_empty());
+        assert_eq!(plan.unchanged, 1);
+    }
+
+    #[test]
+    fn changed_description_is_updated() {
+        let mut fields = registered_options();
+        fields["description"] = json!("Shows a different number");
+
+        let plan = diff_commands(SCOPE, vec![registered(10, "number", fields)], vec![builder()]);
+
+        assert_eq!(plan.unchanged, 0);
+        assert!(matches!(plan.changes.as_slice(), [CommandChange::Update(id, _)] if *id == CommandId::new(10)));
+    }
+
+    #[test]
+    fn localizations_are_ignored() {
+        let command = CreateCommand::new("number")
+            .description("Shows a number")
+            .description_localized("de", "Zeigt eine Zahl")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "max", "Highest number")
+                    .name_localized("de", "hoechste")
+                    .min_int_value(1)
+            );
+
+        let plan = diff_commands(SCOPE, vec![registered(10, "number", registered_options())], vec![command]);
+
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn int_and_float_bounds_match() {
+        let fields = json!({ "options": [{ "type": 4, "name": "max", "description": "Highest number", "min_value": 1.0 }] });
+        let plan = diff_commands(SCOPE, vec![registered(10, "number", fields)], vec![builder()]);
+
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn changed_bound_is_updated() {
+        let fields = json!({ "options": [{ "type": 4, "name": "max", "description": "Highest number", "min_value": 2 }] });
+        let plan = diff_commands(SCOPE, vec![registered(10, "number", fields)], vec![builder()]);
+
+        assert!(matches!(plan.changes.as_slice(), [CommandChange::Update(..)]));
+    }
+
+    #[test]
+    fn missing_command_is_created() {
+        let plan = diff_commands(SCOPE, Vec::new(), vec![builder()]);
+
+        assert!(matches!(plan.changes.as_slice(), [CommandChange::Create(_)]));
+        assert_eq!(plan.changes[0].get_name(), "number");
+    }
+
+    #[test]
+    fn leftover_command_is_deleted() {
+        let existing = vec![
+            registered(10, "number", registered_options()),
+            registered(11, "old", json!({}))
+        ];
+
+        let plan = diff_commands(SCOPE, existing, vec![builder()]);
+
+        assert_eq!(plan.unchanged, 1);
+        assert!(matches!(plan.changes.as_slice(), [CommandChange::Delete(id, name)] if *id == CommandId::new(11) && name == "old"));
+    }
+}
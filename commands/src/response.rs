@@ -0,0 +1,264 @@
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+
+use serenity::all::{CommandInteraction, ComponentInteraction};
+use serenity::builder::{
+    CreateActionRow,
+    CreateAttachment,
+    CreateEmbed,
+    CreateInteractionResponse,
+    CreateInteractionResponseFollowup,
+    CreateInteractionResponseMessage,
+    CreateMessage,
+    EditInteractionResponse
+};
+use serenity::http::Http;
+
+/// A message sent in reply to a slash command
+#[derive(Default, Clone)]
+pub struct SlashMessage {
+    content: String,
+    ephemeral: bool,
+    attachments: Vec<CreateAttachment>,
+    embeds: Vec<CreateEmbed>,
+    components: Vec<CreateActionRow>,
+}
+
+impl SlashMessage {
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Only show the message to the user who ran the command
+    pub fn ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    pub fn add_file(mut self, file: CreateAttachment) -> Self {
+        self.attachments.push(file);
+        self
+    }
+
+    pub fn add_embed(mut self, embed: CreateEmbed) -> Self {
+        self.embeds.push(embed);
+        self
+    }
+
+    /// Adds a row of buttons or menus below the message
+    pub fn add_row(mut self, row: CreateActionRow) -> Self {
+        self.components.push(row);
+        self
+    }
+
+    fn to_message(&self) -> CreateInteractionResponseMessage {
+        let mut message = CreateInteractionResponseMessage::new()
+            .ephemeral(self.ephemeral)
+            .add_files(self.attachments.clone())
+            .embeds(self.embeds.clone())
+            .components(self.components.clone());
+
+        // discord rejects empty content, but embeds can stand alone
+        if !self.content.is_empty() {
+            message = message.content(&self.content);
+        }
+
+        message
+    }
+
+    /// Edits replace the whole message, so everything is set
+    /// even when empty, to clear what was there before
+    fn to_edit(&self) -> EditInteractionResponse {
+        let mut edit = EditInteractionResponse::new()
+            .content(&self.content)
+            .clear_attachments()
+            .embeds(self.embeds.clone())
+            .components(self.components.clone());
+
+        for attachment in &self.attachments {
+            edit = edit.new_attachment(attachment.clone());
+        }
+
+        edit
+    }
+
+    fn to_followup(&self) -> CreateInteractionResponseFollowup {
+        let mut followup = CreateInteractionResponseFollowup::new()
+            .ephemeral(self.ephemeral)
+            .add_files(self.attachments.clone())
+            .embeds(self.embeds.clone())
+            .components(self.components.clone());
+
+        if !self.content.is_empty() {
+            followup = followup.content(&self.content);
+        }
+
+        followup
+    }
+
+    /// Builds a normal message with the same contents, for prefix commands.
+    /// Messages can't be ephemeral, so that is ignored.
+    pub fn to_create_message(&self) -> CreateMessage {
+        let mut message = CreateMessage::new()
+            .add_files(self.attachments.clone())
+            .embeds(self.embeds.clone())
+            .components(self.components.clone());
+
+        if !self.content.is_empty() {
+            message = message.content(&self.content);
+        }
+
+        message
+    }
+}
+
+/// The reply to a slash command, plus any follow-up messages
+/// sent after it, e.g. for results too long for one message
+#[derive(Default, Clone)]
+pub struct SlashResponse {
+    reply: SlashMessage,
+    follow_ups: Vec<SlashMessage>,
+}
+
+impl SlashResponse {
+    pub fn new(content: impl Into<String>) -> Self {
+        Self::from(SlashMessage::new(content))
+    }
+
+    /// Only show the reply to the user who ran the command
+    pub fn ephemeral(mut self, ephemeral: bool) -> Self {
+        self.reply = self.reply.ephemeral(ephemeral);
+        self
+    }
+
+    pub fn add_file(mut self, file: CreateAttachment) -> Self {
+        self.reply = self.reply.add_file(file);
+        self
+    }
+
+    pub fn add_embed(mut self, embed: CreateEmbed) -> Self {
+        self.reply = self.reply.add_embed(embed);
+        self
+    }
+
+    /// Adds a row of buttons or menus below the reply
+    pub fn add_row(mut self, row: CreateActionRow) -> Self {
+        self.reply = self.reply.add_row(row);
+        self
+    }
+
+    /// Sends `message` after the reply
+    pub fn follow_up(mut self, message: SlashMessage) -> Self {
+        self.follow_ups.push(message);
+        self
+    }
+
+    pub fn get_reply(&self) -> &SlashMessage { &self.reply }
+
+    pub fn get_follow_ups(&self) -> &[SlashMessage] { &self.follow_ups }
+}
+
+impl From<SlashMessage> for SlashResponse {
+    fn from(reply: SlashMessage) -> Self {
+        Self {
+            reply,
+            follow_ups: Vec::new()
+        }
+    }
+}
+
+/// Runs `run` and replies to `command` with its response.
+///
+/// If `run` takes longer than `defer_after`, the reply is deferred so
+/// discord's 3 second deadline isn't missed, then edited in once ready.
+/// A deferred reply can't change whether it is ephemeral, so it's
+/// deferred as `defer_ephemeral`, and a public deferral is swapped
+/// for an ephemeral follow-up if the response turns out ephemeral.
+pub async fn respond(
+    http: &Http,
+    command: &CommandInteraction,
+    defer_after: Duration,
+    defer_ephemeral: bool,
+    run: impl Future<Output = SlashResponse>
+) -> Result<(), serenity::Error> {
+    let mut run = pin!(run);
+
+    let response = match tokio::time::timeout(defer_after, &mut run).await {
+        Ok(response) => {
+            command.create_response(http, CreateInteractionResponse::Message(response.reply.to_message())).await?;
+            response
+        },
+        Err(_elapsed) => {
+            let defer = CreateInteractionResponseMessage::new().ephemeral(defer_ephemeral);
+            command.create_response(http, CreateInteractionResponse::Defer(defer)).await?;
+
+            let response = run.await;
+
+            if response.reply.ephemeral && !defer_ephemeral {
+                command.delete_response(http).await?;
+                command.create_followup(http, response.reply.to_followup()).await?;
+            } else {
+                command.edit_response(http, response.reply.to_edit()).await?;
+            }
+
+            response
+        }
+    };
+
+    for follow_up in &response.follow_ups {
+        command.create_followup(http, follow_up.to_followup()).await?;
+    }
+
+    Ok(())
+}
+
+/// Runs `run` and replaces the message `component` is on with its
+/// response, e.g. to show a new image when a button is pressed.
+///
+/// Like [`respond`], the update is deferred if `run` takes longer than
+/// `defer_after`. An ephemeral response is sent as a new message to
+/// the user instead, leaving the original message as it was.
+pub async fn update(
+    http: &Http,
+    component: &ComponentInteraction,
+    defer_after: Duration,
+    run: impl Future<Output = SlashResponse>
+) -> Result<(), serenity::Error> {
+    let mut run = pin!(run);
+
+    let response = match tokio::time::timeout(defer_after, &mut run).await {
+        Ok(response) => {
+            let reply = if response.reply.ephemeral {
+                CreateInteractionResponse::Message(response.reply.to_message())
+            } else {
+                CreateInteractionResponse::UpdateMessage(response.reply.to_message())
+            };
+
+            component.create_response(http, reply).await?;
+            response
+        },
+        Err(_elapsed) => {
+            component.create_response(http, CreateInteractionResponse::Acknowledge).await?;
+
+            let response = run.await;
+
+            if response.reply.ephemeral {
+                component.create_followup(http, response.reply.to_followup()).await?;
+            } else {
+                component.edit_response(http, response.reply.to_edit()).await?;
+            }
+
+            response
+        }
+    };
+
+    for follow_up in &response.follow_ups {
+        component.create_followup(http, follow_up.to_followup()).await?;
+    }
+
+    Ok(())
+}
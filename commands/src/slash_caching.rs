@@ -0,0 +1,303 @@
+use bot_data::config::{Config, MAX_SCRAMBLR_TRIES};
+use bot_data::settings::{Settings, SettingsData, GuildLimits, get_guild_channels};
+use bot_data::user_message_cache::{UserMessageCache, UserMessageData};
+use serenity::all::{ChannelType, CommandInteraction, CommandOptionType, ResolvedOption, ResolvedValue};
+use serenity::async_trait;
+use serenity::builder::{CreateCommand, CreateCommandOption};
+use serenity::cache::Cache;
+use serenity::model::id::GuildId;
+use serenity::model::Permissions;
+
+use crate::response::SlashResponse;
+use crate::slash_command::{SlashCommand, SlashContext};
+
+pub struct Caching;
+
+#[async_trait]
+impl SlashCommand for Caching {
+    fn name(&self) -> &str { "caching" }
+
+    fn register(&self) -> CreateCommand { register() }
+
+    async fn run(&self, ctx: &SlashContext<'_>, command: &CommandInteraction) -> SlashResponse {
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+        let settings_lock = ctx.get_data::<SettingsData>().await;
+
+        // settings before the message cache, like everywhere else
+        let mut settings = settings_lock.write().await;
+        let mut user_message_cache = msgs_lock.write().await;
+
+        let content = run(
+            ctx.cache,
+            command,
+            &mut user_message_cache,
+            &mut settings,
+            &ctx.config,
+            &command.data.options()
+        );
+
+        SlashResponse::new(content)
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("caching")
+        .description("Choose which channels of this server have their messages cached")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .dm_permission(false)
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "exclude",
+            "Stop caching a channel or category, and delete its cached messages"
+        ).add_sub_option(channel_option()))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "include",
+            "Cache a channel or category, even if it would otherwise be excluded"
+        ).add_sub_option(channel_option()))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "reset",
+            "Remove the rule set on a channel or category"
+        ).add_sub_option(channel_option()))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "default",
+            "Choose whether channels without a rule are cached"
+        ).add_sub_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "enabled",
+            "Whether channels without a rule are cached"
+        ).required(true)))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "cross-server",
+            "Choose whether commands like /scramblr may use messages from other servers"
+        ).add_sub_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "enabled",
+            "Whether messages may be mixed with other servers that allow it"
+        ).required(true)))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "limits",
+            "Override the bot's limits for this server. Set a limit to 0 to use the default again"
+        )
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "min-words",
+            "Fewest words a message needs to be cached"
+        ).min_int_value(0))
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "scramblr-min-messages",
+            "Fewest cached messages each user needs for /scramblr"
+        ).min_int_value(0))
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "scramblr-tries",
+            "Message pairs /scramblr tries before giving up"
+        ).min_int_value(0).max_int_value(MAX_SCRAMBLR_TRIES as u64)))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "status",
+            "List the caching rules for this server"
+        ))
+}
+
+fn channel_option() -> CreateCommandOption {
+    CreateCommandOption::new(
+        CommandOptionType::Channel,
+        "channel",
+        "The channel or category"
+    )
+    .channel_types(vec![
+        ChannelType::Text,
+        ChannelType::News,
+        ChannelType::Voice,
+        ChannelType::Stage,
+        ChannelType::Forum,
+        ChannelType::Category,
+        ChannelType::PublicThread,
+        ChannelType::PrivateThread,
+        ChannelType::NewsThread
+    ])
+    .required(true)
+}
+
+pub fn run(
+    cache: &Cache,
+    command: &CommandInteraction,
+    user_message_cache: &mut UserMessageCache,
+    settings: &mut Settings,
+    config: &Config,
+    options: &[ResolvedOption<'_>]
+) -> String {
+    let guild_id = match command.guild_id {
+        Some(guild_id) => guild_id,
+        None => return "This command can only be used in a server".to_string()
+    };
+
+    // discord hides the command from members without the permission,
+    // but server admins can override that in their integration settings
+    let can_manage = command.member.as_ref()
+        .and_then(|member| member.permissions)
+        .map(|permissions| permissions.manage_guild())
+        .unwrap_or(false);
+
+    if !can_manage {
+        return "You need the Manage Server permission to change caching settings".to_string();
+    }
+
+    let (subcommand, sub_options) = match options.first() {
+        Some(ResolvedOption {
+            name,
+            value: ResolvedValue::SubCommand(sub_options),
+            ..
+        }) => (*name, sub_options.as_slice()),
+        _ => ("status", [].as_slice())
+    };
+
+    let channel_id = sub_options.iter().find_map(|option| match option.value {
+        ResolvedValue::Channel(channel) => Some(channel.id.get()),
+        _ => None
+    });
+
+    let enabled = sub_options.iter().find_map(|option| match option.value {
+        ResolvedValue::Boolean(enabled) => Some(enabled),
+        _ => None
+    });
+
+    let result = match (subcommand, channel_id, enabled) {
+        ("exclude", Some(channel_id), _) => settings.set_channel_rule(guild_id.get(), channel_id, Some(false))
+            .map(|_| format!("<#{channel_id}> will no longer be cached")),
+        ("include", Some(channel_id), _) => settings.set_channel_rule(guild_id.get(), channel_id, Some(true))
+            .map(|_| format!("<#{channel_id}> will now be cached")),
+        ("reset", Some(channel_id), _) => settings.set_channel_rule(guild_id.get(), channel_id, None)
+            .map(|_| format!("<#{channel_id}> now follows the server's caching rules")),
+        ("default", _, Some(enabled)) => settings.set_cache_by_default(guild_id.get(), enabled)
+            .map(|_| if enabled {
+                "Channels without a rule will now be cached".to_string()
+            } else {
+                "Channels without a rule will no longer be cached".to_string()
+            }),
+        ("cross-server", _, Some(enabled)) => {
+            return match settings.set_cross_guild_allowed(guild_id.get(), enabled) {
+                Ok(_) if enabled => "Messages may now be mixed with other servers that allow it".to_string(),
+                Ok(_) => "Messages will only be mixed within this server".to_string(),
+                Err(e) => e.to_string()
+            };
+        },
+        ("limits", _, _) => return set_limits(settings, config, guild_id, sub_options),
+        _ => return get_status(settings, config, guild_id)
+    };
+
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => return e.to_string()
+    };
+
+    match purge_uncached_channels(cache, guild_id, user_message_cache, settings, channel_id) {
+        Ok(_) => response,
+        Err(e) => format!("{response}, but cached messages could not be deleted: {e}")
+    }
+}
+
+/// Removes the cached messages of every channel in `guild_id` that is
+/// no longer cached. `changed_channel_id` is checked even if it isn't
+/// in the serenity cache, e.g. an archived thread.
+fn purge_uncached_channels(
+    cache: &Cache,
+    guild_id: GuildId,
+    user_message_cache: &mut UserMessageCache,
+    settings: &Settings,
+    changed_channel_id: Option<u64>
+) -> Result<(), bot_data::user_message_cache::MessageCacheError> {
+    let mut channels = get_guild_channels(cache, guild_id);
+
+    if let Some(channel_id) = changed_channel_id {
+        if !channels.iter().any(|(id, _)| *id == channel_id) {
+            channels.push((channel_id, Vec::new()));
+        }
+    }
+
+    for (channel_id, parent_ids) in channels {
+        if !settings.is_channel_cached(guild_id.get(), channel_id, &parent_ids) {
+            user_message_cache.remove_messages_in_channel(channel_id)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies the limits given in `options`, where `0` clears an override
+fn set_limits(settings: &mut Settings, config: &Config, guild_id: GuildId, options: &[ResolvedOption<'_>]) -> String {
+    let mut limits = settings.get_guild_limits(guild_id.get());
+
+    for option in options {
+        let value = match option.value {
+            ResolvedValue::Integer(0) => None,
+            ResolvedValue::Integer(value) => match usize::try_from(value) {
+                Ok(value) => Some(value),
+                Err(_e) => return format!("`{}` can't be negative", option.name)
+            },
+            _ => continue
+        };
+
+        match option.name {
+            "min-words" => limits.min_words = value,
+            "scramblr-min-messages" => {
+                let max_user_messages = config.get_cache_config().get_max_user_messages();
+
+                if value.is_some_and(|value| value > max_user_messages) {
+                    return format!("`scramblr-min-messages` can't be more than the {max_user_messages} messages cached per user");
+                }
+
+                limits.scramblr_min_messages = value;
+            },
+            "scramblr-tries" => limits.scramblr_max_tries = value,
+            _ => {}
+        }
+    }
+
+    match settings.set_guild_limits(guild_id.get(), limits) {
+        Ok(_) => format_limits(config, &limits),
+        Err(e) => e.to_string()
+    }
+}
+
+fn format_limits(config: &Config, limits: &GuildLimits) -> String {
+    let describe = |value: Option<usize>, default: usize| match value {
+        Some(value) => value.to_string(),
+        None => format!("{default} (default)")
+    };
+
+    format!(
+        "Minimum words: {}\n/scramblr minimum messages: {}\n/scramblr tries: {}",
+        describe(limits.min_words, config.get_cache_config().get_min_words()),
+        describe(limits.scramblr_min_messages, config.get_scramblr_config().get_min_messages()),
+        describe(limits.scramblr_max_tries, config.get_scramblr_config().get_max_tries())
+    )
+}
+
+fn get_status(settings: &Settings, config: &Config, guild_id: GuildId) -> String {
+    let guild = settings.get_guild(guild_id.get());
+
+    let list = |channels: &[u64]| if channels.is_empty() {
+        "none".to_string()
+    } else {
+        channels.iter()
+            .map(|id| format!("<#{id}>"))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    format!(
+        "Channels without a rule are {}cached\nMessages are {}mixed with other servers\nIncluded: {}\nExcluded: {}\n{}",
+        if guild.cache_by_default { "" } else { "not " },
+        if guild.allow_cross_guild { "" } else { "not " },
+        list(&guild.included_channels),
+        list(&guild.excluded_channels),
+        format_limits(config, &guild.limits)
+    )
+}
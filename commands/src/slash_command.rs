@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use bot_data::config::Config;
+use serenity::all::{AutocompleteChoice, CommandInteraction, ComponentInteraction};
+use serenity::async_trait;
+use serenity::builder::CreateCommand;
+use serenity::cache::Cache;
+use serenity::client::Context;
+use serenity::http::Http;
+use serenity::prelude::{RwLock, TypeMap, TypeMapKey};
+
+use crate::response::SlashResponse;
+
+/// Everything a slash command may need while running
+pub struct SlashContext<'a> {
+    pub cache: &'a Arc<Cache>,
+    pub http: &'a Arc<Http>,
+    pub config: Arc<Config>,
+    data: &'a Arc<RwLock<TypeMap>>,
+}
+
+impl<'a> SlashContext<'a> {
+    pub fn new(ctx: &'a Context, config: Arc<Config>) -> Self {
+        Self {
+            cache: &ctx.cache,
+            http: &ctx.http,
+            config,
+            data: &ctx.data
+        }
+    }
+
+    /// Returns a clone of the value stored under `T`, usually a lock.
+    ///
+    /// Panics if nothing is stored under `T`, like the rest of the bot.
+    pub async fn get_data<T>(&self) -> T::Value
+    where
+        T: TypeMapKey,
+        T::Value: Clone
+    {
+        let data_read = self.data.read().await;
+
+        data_read.get::<T>()
+            .unwrap_or_else(|| panic!("Expected {}", std::any::type_name::<T>()))
+            .clone()
+    }
+
+    /// Replaces the value stored under `T`, e.g. after a reload
+    pub async fn insert_data<T: TypeMapKey>(&self, value: T::Value) {
+        self.data.write().await.insert::<T>(value);
+    }
+}
+
+/// A slash command, registered with discord and dispatched by name
+#[async_trait]
+pub trait SlashCommand: Send + Sync {
+    /// Returns the name the command is registered under
+    fn name(&self) -> &str;
+
+    /// Returns the command to register with discord
+    fn register(&self) -> CreateCommand;
+
+    /// Returns `true` if the reply should be deferred as ephemeral,
+    /// for commands that reply privately but may be slow
+    fn is_ephemeral(&self) -> bool { false }
+
+    /// Runs the command and returns the response to send
+    async fn run(&self, ctx: &SlashContext<'_>, command: &CommandInteraction) -> SlashResponse;
+
+    /// Returns suggestions for the option the user is typing in
+    async fn autocomplete(&self, _ctx: &SlashContext<'_>, _command: &CommandInteraction) -> Vec<AutocompleteChoice> {
+        Vec::new()
+    }
+
+    /// Handles a button on one of the command's replies being pressed.
+    /// `data` is the button's custom id after `"<name>:"`.
+    async fn handle_component(&self, _ctx: &SlashContext<'_>, _component: &ComponentInteraction, _data: &str) -> SlashResponse {
+        SlashResponse::new("This button no longer works").ephemeral(true)
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serenity::all::{AutocompleteChoice, ButtonStyle};
+use serenity::async_trait;
+use serenity::builder::{CreateActionRow, CreateAttachment, CreateButton, CreateEmbed, CreateEmbedFooter};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::fetch_client::{FetchClient, FetchClientData};
+use crate::hybrid::{ArgKind, ArgSpec, HybridArgs, HybridCommand, Invocation};
+use crate::image_provider::{Breed, Image, ImageProvider};
+use crate::response::SlashResponse;
+use crate::slash_command::SlashContext;
+
+/// Most choices discord will show for an autocompleted option
+const MAX_CHOICES: usize = 25;
+
+/// Wait before trying again after a breed list fails to fetch,
+/// if the list's own refresh interval is longer
+const BREED_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+const BREED_ARGS: &[ArgSpec] = &[ArgSpec {
+    name: "breed",
+    description: "The breed to show",
+    kind: ArgKind::Text,
+    required: false,
+    autocomplete: true
+}];
+
+/// A command posting a random image from a provider, e.g. `/cat`
+pub struct ImageCommand {
+    description: String,
+    provider: Arc<dyn ImageProvider>,
+
+    /// Never sent on, only closed when the command is dropped,
+    /// which stops its breed refresh task
+    dropped: watch::Sender<()>,
+}
+
+impl ImageCommand {
+    pub fn new(description: String, provider: Box<dyn ImageProvider>) -> Self {
+        let (dropped, _) = watch::channel(());
+
+        Self { description, provider: Arc::from(provider), dropped }
+    }
+
+    /// Fetches the provider's breed list now, then again every refresh
+    /// interval, in the background. Autocomplete only reads the fetched
+    /// list, so it never misses discord's deadline waiting for it.
+    ///
+    /// The task stops once the command is dropped, e.g. by a reload.
+    pub fn spawn_breed_refresh(&self, client: Arc<FetchClient>) -> Option<JoinHandle<()>> {
+        let refresh_interval = self.provider.get_breed_refresh_interval()?;
+
+        let provider = self.provider.clone();
+        let mut dropped = self.dropped.subscribe();
+
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(refresh_interval);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = dropped.changed() => break
+                }
+
+                if let Err(e) = provider.refresh_breeds(&client).await {
+                    println!("Cannot refresh {} breeds: {e}", provider.name());
+
+                    // the old list, if any, is kept until this works
+                    interval.reset_after(refresh_interval.min(BREED_RETRY_WAIT));
+                }
+            }
+        }))
+    }
+
+    /// Finds the breed `query` names, by id or name. Only breeds in
+    /// the list are found, as their ids go into the request url.
+    async fn find_breed(&self, query: &str) -> Result<Breed, String> {
+        let breeds = match self.provider.get_breeds().await {
+            Ok(breeds) => breeds,
+            Err(e) => {
+                println!("Cannot get {} breeds: {e}", self.provider.name());
+                return Err(format!("Cannot look up {} breeds right now, try again without one", self.provider.name()));
+            }
+        };
+
+        let found = breeds.iter()
+            .find(|breed| breed.id.eq_ignore_ascii_case(query) || breed.name.eq_ignore_ascii_case(query));
+
+        match found {
+            Some(breed) => Ok(breed.clone()),
+            None => Err(format!("There is no {} breed called `{query}`", self.provider.name()))
+        }
+    }
+
+    /// Fetches an image and builds the reply showing it
+    async fn reply(&self, client: &FetchClient, breed: Option<&Breed>) -> Result<SlashResponse, String> {
+        let image = match self.provider.fetch_image(client, breed).await {
+            Ok(image) => image,
+            Err(e) => return Err(e.to_string())
+        };
+
+        // the breed's id is kept in the button, so the next image is the same breed
+        let custom_id = format!("{}:another:{}", self.provider.name(), breed.map(|breed| breed.id.as_str()).unwrap_or_default());
+
+        let button = CreateButton::new(custom_id)
+            .label("Another one")
+            .style(ButtonStyle::Secondary);
+
+        let mut response = SlashResponse::new("")
+            .add_embed(build_embed(&image))
+            .add_row(CreateActionRow::Buttons(vec![button]));
+
+        // the embed points at the file with an `attachment://` url
+        if let Some(path) = &image.file {
+            match CreateAttachment::path(path).await {
+                Ok(file) => response = response.add_file(file),
+                Err(e) => return Err(format!("Could not read {}: {e}", path.display()))
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl HybridCommand for ImageCommand {
+    fn name(&self) -> &str { self.provider.name() }
+
+    fn description(&self) -> &str { &self.description }
+
+    fn args(&self) -> &'static [ArgSpec] {
+        if self.provider.has_breeds() { BREED_ARGS } else { &[] }
+    }
+
+    async fn run(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, args: &HybridArgs) -> SlashResponse {
+        let client = ctx.get_data::<FetchClientData>().await;
+
+        let breed = match args.get_string("breed") {
+            Some(query) => match self.find_breed(query).await {
+                Ok(breed) => Some(breed),
+                Err(e) => return SlashResponse::new(e).ephemeral(true)
+            },
+            None => None
+        };
+
+        match self.reply(&client, breed.as_ref()).await {
+            Ok(response) => response,
+            Err(e) => SlashResponse::new(e)
+        }
+    }
+
+    async fn autocomplete(&self, _ctx: &SlashContext<'_>, _arg: &str, value: &str) -> Vec<AutocompleteChoice> {
+        // nothing to suggest until the list has been fetched
+        let breeds = match self.provider.get_breeds().await {
+            Ok(breeds) => breeds,
+            Err(_e) => return Vec::new()
+        };
+
+        let value = value.to_lowercase();
+
+        breeds.iter()
+            .filter(|breed| breed.name.to_lowercase().contains(&value) || breed.id.to_lowercase().contains(&value))
+            .take(MAX_CHOICES)
+            .map(|breed| AutocompleteChoice::new(breed.name.clone(), breed.id.clone()))
+            .collect()
+    }
+
+    async fn handle_component(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, data: &str) -> SlashResponse {
+        let breed_id = match data.strip_prefix("another:") {
+            Some(breed_id) => breed_id,
+            None => return SlashResponse::new("This button no longer works").ephemeral(true)
+        };
+
+        let client = ctx.get_data::<FetchClientData>().await;
+
+        let breed = if breed_id.is_empty() {
+            None
+        } else {
+            match self.find_breed(breed_id).await {
+                Ok(breed) => Some(breed),
+                Err(e) => return SlashResponse::new(e).ephemeral(true)
+            }
+        };
+
+        // keep the old image up if the new one can't be fetched
+        match self.reply(&client, breed.as_ref()).await {
+            Ok(response) => response,
+            Err(e) => SlashResponse::new(e).ephemeral(true)
+        }
+    }
+}
+
+/// Shows the image large, titled with its breed, with any credit below
+pub fn build_embed(image: &Image) -> CreateEmbed {
+    let mut embed = CreateEmbed::new().image(&image.url);
+
+    if let Some(breed) = &image.breed {
+        embed = embed.title(breed);
+    }
+
+    if let Some(link) = &image.link {
+        embed = embed.description(format!("[Source]({link})"));
+    }
+
+    if let Some(attribution) = &image.attribution {
+        embed = embed.footer(CreateEmbedFooter::new(attribution));
+    }
+
+    embed
+}
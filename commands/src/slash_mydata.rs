@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bot_data::user_message_cache::{UserMessageCache, UserMessageData};
+use serenity::all::CommandInteraction;
+use serenity::async_trait;
+use serenity::builder::{CreateAttachment, CreateCommand};
+use serenity::model::user::User;
+use serenity::prelude::{Mutex, TypeMapKey};
+use serde_json::json;
+
+use crate::response::SlashResponse;
+use crate::slash_command::{SlashCommand, SlashContext};
+
+/// How long a user has to wait between exports
+pub const EXPORT_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// When each user last exported their data
+#[derive(Default)]
+pub struct ExportCooldowns {
+    // <user_id, last export>
+    last_exports: HashMap<u64, Instant>
+}
+
+impl ExportCooldowns {
+    /// Records an export by `user_id` if they are off cooldown,
+    /// otherwise returns how long they have left to wait
+    pub fn try_start(&mut self, user_id: u64) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        // forget anyone whose cooldown has run out
+        self.last_exports.retain(|_, last| now.duration_since(*last) < EXPORT_COOLDOWN);
+
+        match self.last_exports.get(&user_id) {
+            Some(last) => Err(EXPORT_COOLDOWN - now.duration_since(*last)),
+            None => {
+                self.last_exports.insert(user_id, now);
+
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct ExportCooldownData;
+
+impl TypeMapKey for ExportCooldownData {
+    type Value = Arc<Mutex<ExportCooldowns>>;
+}
+
+pub struct MyData;
+
+#[async_trait]
+impl SlashCommand for MyData {
+    fn name(&self) -> &str { "mydata" }
+
+    fn register(&self) -> CreateCommand { register() }
+
+    fn is_ephemeral(&self) -> bool { true }
+
+    async fn run(&self, ctx: &SlashContext<'_>, command: &CommandInteraction) -> SlashResponse {
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+        let cooldowns_lock = ctx.get_data::<ExportCooldownData>().await;
+
+        let user_message_cache = msgs_lock.read().await;
+        let mut cooldowns = cooldowns_lock.lock().await;
+
+        run(&command.user, &user_message_cache, &mut cooldowns)
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("mydata").description("Get a copy of every message of yours the bot has cached")
+}
+
+pub fn run(
+    msg_author: &User,
+    user_message_cache: &UserMessageCache,
+    cooldowns: &mut ExportCooldowns
+) -> SlashResponse {
+    let user_id = msg_author.id.get();
+
+    if let Err(remaining) = cooldowns.try_start(user_id) {
+        let minutes = remaining.as_secs() / 60 + 1;
+
+        return SlashResponse::new(format!("You can only export your data every {} minutes, try again in {minutes} minute(s)", EXPORT_COOLDOWN.as_secs() / 60)).ephemeral(true);
+    }
+
+    let mut cached_messages = match user_message_cache.get_user_messages(user_id) {
+        Ok(messages) => messages,
+        Err(e) => return SlashResponse::new(e.to_string()).ephemeral(true)
+    };
+
+    cached_messages.sort_by_key(|msg| msg.time);
+
+    let mut failed = 0;
+
+    let messages = cached_messages.iter()
+        .filter_map(|msg| match user_message_cache.decrypt_message(user_id, msg) {
+            Ok(content) => Some(json!({
+                "id": msg.id,
+                "channel_id": msg.channel_id,
+                "timestamp": msg.time,
+                "content": content
+            })),
+            Err(_) => {
+                failed += 1;
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let export = json!({
+        "user_id": user_id.to_string(),
+        "message_count": messages.len(),
+        "messages": messages
+    });
+
+    let contents = match serde_json::to_vec_pretty(&export) {
+        Ok(contents) => contents,
+        Err(e) => return SlashResponse::new(e.to_string()).ephemeral(true)
+    };
+
+    let mut summary = format!("Here are the {} message(s) of yours currently cached", messages.len());
+
+    if failed > 0 {
+        summary.push_str(&format!("\n{failed} message(s) could not be decrypted and were left out"));
+    }
+
+    SlashResponse::new(summary)
+        .ephemeral(true)
+        .add_file(CreateAttachment::bytes(contents, format!("rittou-data-{user_id}.json")))
+}
@@ -0,0 +1,94 @@
+use bot_data::settings::{Settings, SettingsData};
+use bot_data::user_message_cache::{UserMessageCache, UserMessageData};
+use serenity::all::{CommandInteraction, CommandOptionType, ResolvedOption, ResolvedValue};
+use serenity::async_trait;
+use serenity::builder::{CreateCommand, CreateCommandOption};
+use serenity::model::user::User;
+
+use crate::response::SlashResponse;
+use crate::slash_command::{SlashCommand, SlashContext};
+
+pub struct Privacy;
+
+#[async_trait]
+impl SlashCommand for Privacy {
+    fn name(&self) -> &str { "privacy" }
+
+    fn register(&self) -> CreateCommand { register() }
+
+    async fn run(&self, ctx: &SlashContext<'_>, command: &CommandInteraction) -> SlashResponse {
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+        let settings_lock = ctx.get_data::<SettingsData>().await;
+
+        // settings are always locked before the message cache,
+        // as when caching messages, so the two can't deadlock
+        let mut settings = settings_lock.write().await;
+        let mut user_message_cache = msgs_lock.write().await;
+
+        let content = run(&command.user, &mut user_message_cache, &mut settings, &command.data.options());
+
+        SlashResponse::new(content)
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("privacy")
+        .description("Choose whether your messages can be cached")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "opt-in",
+            "Allow your messages to be cached for commands like /scramblr"
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "opt-out",
+            "Stop caching your messages and delete any that are cached"
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "status",
+            "Check whether your messages are being cached"
+        ))
+}
+
+pub fn run(
+    msg_author: &User,
+    user_message_cache: &mut UserMessageCache,
+    settings: &mut Settings,
+    options: &[ResolvedOption<'_>]
+) -> String {
+    let user_id = msg_author.id.get();
+
+    let subcommand = match options.first() {
+        Some(ResolvedOption {
+            name,
+            value: ResolvedValue::SubCommand(_),
+            ..
+        }) => *name,
+        _ => "status"
+    };
+
+    match subcommand {
+        "opt-in" => match settings.set_caching_enabled(user_id, true) {
+            Ok(_) => "Your messages will now be cached".to_string(),
+            Err(e) => e.to_string()
+        },
+        "opt-out" => {
+            if let Err(e) = settings.set_caching_enabled(user_id, false) {
+                return e.to_string();
+            }
+
+            match user_message_cache.forget_user(user_id) {
+                Ok(_) => "Your messages will no longer be cached, and any cached messages have been deleted".to_string(),
+                Err(e) => format!("You have been opted out, but your cached messages could not be deleted: {e}")
+            }
+        },
+        _ => {
+            if settings.is_caching_enabled(user_id) {
+                "Your messages are being cached. Use `/privacy opt-out` to stop".to_string()
+            } else {
+                "Your messages are not being cached. Use `/privacy opt-in` to allow it".to_string()
+            }
+        }
+    }
+}
@@ -1,33 +1,75 @@
-use bot_data::config::Config;
 use bot_data::scramblr::get_scrambled_message;
-use bot_data::user_message_cache::UserMessageCache;
-use serenity::all::{CommandOptionType, ResolvedOption, ResolvedValue};
-use serenity::builder::{CreateCommand, CreateCommandOption};
+use bot_data::config::ScramblrConfig;
+use bot_data::settings::{Settings, SettingsData};
+use bot_data::user_message_cache::{UserMessageCache, UserMessageData};
+use serenity::async_trait;
+use serenity::model::id::GuildId;
 use serenity::model::user::User;
 
+use crate::hybrid::{ArgKind, ArgSpec, CommandChecks, HybridArgs, HybridCommand, Invocation};
+use crate::response::SlashResponse;
+use crate::slash_command::SlashContext;
 
-pub fn register() -> CreateCommand {
-    CreateCommand::new("scramblr")
-        .description("Scramble up your messages and make a new one!")
-        .add_option(CreateCommandOption::new(
-            CommandOptionType::User,
-            "user",
-            "The user to scramble your messages with"
-        ).required(false)
-    )
+
+pub struct Scramblr;
+
+#[async_trait]
+impl HybridCommand for Scramblr {
+    fn name(&self) -> &str { "scramblr" }
+
+    fn description(&self) -> &str { "Scramble up your messages and make a new one!" }
+
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec {
+            name: "user",
+            description: "The user to scramble your messages with",
+            kind: ArgKind::User,
+            required: false,
+            autocomplete: false
+        }]
+    }
+
+    fn checks(&self) -> CommandChecks {
+        CommandChecks { guild_only: true, ..Default::default() }
+    }
+
+    async fn run(&self, ctx: &SlashContext<'_>, invocation: &Invocation<'_>, args: &HybridArgs) -> SlashResponse {
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+        let settings_lock = ctx.get_data::<SettingsData>().await;
+
+        let author = invocation.author.clone();
+        let other_user = args.get_user("user").cloned();
+        let guild_id = invocation.guild_id;
+        let scramblr_config = ctx.config.get_scramblr_config().clone();
+
+        // decrypting can be slow, so keep it off the async runtime
+        // and let the reply be deferred while it runs
+        let result = tokio::task::spawn_blocking(move || {
+            let settings = settings_lock.blocking_read();
+            let user_message_cache = msgs_lock.blocking_read();
+
+            run(&author, other_user.as_ref(), guild_id, &user_message_cache, &settings, &scramblr_config)
+        }).await;
+
+        match result {
+            Ok(content) => SlashResponse::new(content),
+            Err(e) => SlashResponse::new(format!("Error while scrambling messages: {e}"))
+        }
+    }
 }
 
-pub async fn run(msg_author: &User, user_message_cache: &UserMessageCache, options: &[ResolvedOption<'_>], config: &Config) -> String {
+pub fn run(msg_author: &User, other_user: Option<&User>, guild_id: Option<GuildId>, user_message_cache: &UserMessageCache, settings: &Settings, scramblr_config: &ScramblrConfig) -> String {
     // get user-provided user, or default to message author
-    let provided_user = match options.get(0) {
-        Some(ResolvedOption {
-            value: ResolvedValue::User(mentioned_user, _),
-            ..
-        }) => mentioned_user,
-        _ => msg_author
-    };
-
-    match get_scrambled_message(msg_author, &provided_user, user_message_cache, config) {
+    let provided_user = other_user.unwrap_or(msg_author);
+
+    match get_scrambled_message(
+        msg_author,
+        provided_user,
+        guild_id.map(|guild_id| guild_id.get()),
+        user_message_cache,
+        settings,
+        scramblr_config
+    ) {
         Ok(content) => content,
         Err(scramblr_error) => format!("{}", scramblr_error.to_string())
     }
@@ -1,72 +1,393 @@
-use bot_data::user_message_cache::UserMessageData;
+use std::sync::Arc;
+
+use bot_data::{config::ConfigData, settings::SettingsData, user_message_cache::UserMessageData};
+use crate::fetch_client::{FetchClient, FetchClientData};
+use crate::hybrid::{ArgKind, ArgSpec, CommandChecks, HybridArgs, HybridCommand, Invocation};
+use crate::registry::{CommandScope, get_scopes, sync_commands};
+use crate::{CommandRegistry, CommandRegistryData};
+use crate::response::SlashResponse;
+use crate::slash_command::SlashContext;
 use serenity::{
-    client::Context,
-    framework::standard::{
-        CommandResult,
-        macros::{
-            command,
-            group
-        }
-    },
-    model::channel::Message, builder::EditMessage
+    all::AutocompleteChoice,
+    async_trait,
+    model::{id::GuildId, Permissions}
+};
+
+/// Most prefixes a guild can set
+const MAX_GUILD_PREFIXES: usize = 5;
+
+/// Longest prefix a guild can set, in characters
+const MAX_PREFIX_LEN: usize = 10;
+
+pub struct Ping;
+
+#[async_trait]
+impl HybridCommand for Ping {
+    fn name(&self) -> &str { "ping" }
+
+    fn description(&self) -> &str { "The classic ping-pong" }
+
+    async fn run(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, _args: &HybridArgs) -> SlashResponse {
+        let start = std::time::Instant::now();
+
+        let content = match ctx.http.get_current_user().await {
+            Ok(_) => format!("Pong! Took {}ms to reach discord", start.elapsed().as_millis()),
+            Err(e) => format!("Error while pinging discord: {e}")
+        };
+
+        SlashResponse::new(content)
+    }
+}
+
+const OWNERS_ONLY: CommandChecks = CommandChecks {
+    owners_only: true,
+    guild_only: false,
+    permissions: Permissions::empty()
 };
 
-#[group]
-#[commands(ping, save, load)]
-pub struct Utility;
+pub struct Save;
+
+#[async_trait]
+impl HybridCommand for Save {
+    fn name(&self) -> &str { "save" }
+
+    fn description(&self) -> &str { "Save the message cache" }
+
+    fn checks(&self) -> CommandChecks { OWNERS_ONLY }
+
+    async fn run(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, _args: &HybridArgs) -> SlashResponse {
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+
+        let result = msgs_lock.read().await.save_cache();
+
+        match result {
+            Ok(_) => SlashResponse::new("Saved!"),
+            Err(e) => SlashResponse::new(format!("Error while saving: {e:?}"))
+        }
+    }
+}
+
+pub struct Load;
+
+#[async_trait]
+impl HybridCommand for Load {
+    fn name(&self) -> &str { "load" }
+
+    fn description(&self) -> &str { "Reload the message cache from storage" }
+
+    fn checks(&self) -> CommandChecks { OWNERS_ONLY }
+
+    async fn run(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, _args: &HybridArgs) -> SlashResponse {
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+
+        let result = msgs_lock.write().await.load_cache();
+
+        match result {
+            Ok(_) => SlashResponse::new("Loaded!"),
+            Err(e) => SlashResponse::new(format!("Error while loading: {e:?}"))
+        }
+    }
+}
+
+pub struct Rotate;
+
+#[async_trait]
+impl HybridCommand for Rotate {
+    fn name(&self) -> &str { "rotate" }
+
+    fn description(&self) -> &str { "Re-encrypt every cached message under the current secret key" }
+
+    fn checks(&self) -> CommandChecks { OWNERS_ONLY }
+
+    async fn run(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, _args: &HybridArgs) -> SlashResponse {
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+
+        let result = msgs_lock.write().await.rotate_keys();
+
+        match result {
+            Ok((rewrapped, reencrypted)) => SlashResponse::new(format!("Re-wrapped {rewrapped} data keys and re-encrypted {reencrypted} messages under the current key!")),
+            Err(e) => SlashResponse::new(format!("Error while rotating keys: {e:?}"))
+        }
+    }
+}
+
+pub struct Forget;
+
+#[async_trait]
+impl HybridCommand for Forget {
+    fn name(&self) -> &str { "forget" }
+
+    fn description(&self) -> &str { "Delete a user's cached messages and destroy their data key" }
+
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec {
+            name: "user",
+            description: "The user to forget",
+            kind: ArgKind::User,
+            required: true,
+            autocomplete: false
+        }]
+    }
+
+    fn checks(&self) -> CommandChecks { OWNERS_ONLY }
+
+    async fn run(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, args: &HybridArgs) -> SlashResponse {
+        let user_id = match args.get_user("user") {
+            Some(user) => user.id.get(),
+            None => return SlashResponse::new("Please provide a user mention or id")
+        };
+
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+
+        let result = msgs_lock.write().await.forget_user(user_id);
+
+        match result {
+            Ok(_) => SlashResponse::new("Forgotten!"),
+            Err(e) => SlashResponse::new(format!("Error while forgetting user: {e:?}"))
+        }
+    }
+}
+
+pub struct Reload;
 
-#[command]
-/// The classic ping-pong
-pub async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
-    let start = std::time::Instant::now();
-    let mut pong_msg = msg.reply(ctx, "Waiting...").await?;
-    let elapsed = start.elapsed();
+#[async_trait]
+impl HybridCommand for Reload {
+    fn name(&self) -> &str { "reload" }
 
-    let msg_content = format!("Pong! Took {}ms to respond", elapsed.as_millis());
-    
-    pong_msg.edit(&ctx, EditMessage::new().content(msg_content)).await?;
+    fn description(&self) -> &str { "Re-read the config file and apply everything that can change while running" }
 
-    Ok(())
+    fn checks(&self) -> CommandChecks { OWNERS_ONLY }
+
+    async fn run(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, _args: &HybridArgs) -> SlashResponse {
+        let old_config = ctx.config.clone();
+        let msgs_lock = ctx.get_data::<UserMessageData>().await;
+
+        let (config, mut warnings) = match old_config.reload() {
+            Ok(reloaded) => reloaded,
+            Err(e) => return SlashResponse::new(format!("Error while reloading config, keeping the current one: {e}"))
+        };
+
+        let evicted = {
+            let mut user_message_cache = msgs_lock.write().await;
+            user_message_cache.apply_limits(config.get_cache_config());
+
+            match user_message_cache.apply_retention() {
+                Ok(evicted) => evicted,
+                Err(e) => {
+                    warnings.push(format!("Could not apply the new cache limits: {e}"));
+                    0
+                }
+            }
+        };
+
+        ctx.cache.set_max_messages(config.get_cache_config().get_discord_max_messages());
+
+        // swap in a client with the new timeouts and retries
+        match FetchClient::from_config(config.get_http_config()) {
+            Ok(fetch_client) => ctx.insert_data::<FetchClientData>(Arc::new(fetch_client)).await,
+            Err(e) => warnings.push(format!("Could not apply the new http settings: {e}"))
+        }
+
+        let registry = Arc::new(CommandRegistry::from_config(&config));
+        let dry_run = config.get_commands_config().is_dry_run();
+
+        // take slash commands out of the old dev guild
+        if config.get_dev_guild_id() != old_config.get_dev_guild_id() {
+            if let Some(id) = old_config.get_dev_guild_id() {
+                if let Err(e) = sync_commands(ctx.http, CommandScope::Guild(GuildId::new(*id)), Vec::new(), dry_run).await {
+                    warnings.push(format!("Could not remove commands from the old dev guild: {e}"));
+                }
+            }
+        }
+
+        // image commands may have changed, so sync everywhere
+        for scope in get_scopes(&config) {
+            if let Err(e) = sync_commands(ctx.http, scope, registry.get_slash_commands(), dry_run).await {
+                warnings.push(format!("Could not sync {scope} commands: {e}"));
+            }
+        }
+
+        // the old registry's breed lists stop refreshing once it is dropped
+        registry.spawn_breed_refresh(ctx.get_data::<FetchClientData>().await);
+
+        ctx.insert_data::<CommandRegistryData>(registry).await;
+        ctx.insert_data::<ConfigData>(Arc::new(config)).await;
+
+        let mut content = format!("Reloaded! Evicted {evicted} cached messages under the new limits");
+
+        for warning in warnings {
+            println!("Config reload: {warning}");
+            content.push_str(&format!("\nWarning: {warning}"));
+        }
+
+        SlashResponse::new(content)
+    }
 }
 
-#[command]
-#[owners_only]
-/// The classic ping-pong
-pub async fn save(ctx: &Context, msg: &Message) -> CommandResult {
-    let msgs_lock = {
-        let data_read = ctx.data.read().await;
-
-        data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
-    };
-
-    let _ = match msgs_lock.read().await.save_cache() {
-        Ok(_) => msg.reply(&ctx.http, "Saved!").await,
-        Err(e) => {
-            let aaa = format!("Error while saving: {e:?}");
-            msg.reply(&ctx.http, &aaa).await
+pub struct SyncCommands;
+
+#[async_trait]
+impl HybridCommand for SyncCommands {
+    fn name(&self) -> &str { "sync" }
+
+    fn description(&self) -> &str { "Sync slash commands with the ones registered on discord" }
+
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec {
+            name: "mode",
+            description: "`dry` to only list the changes that would be made",
+            kind: ArgKind::Word,
+            required: false,
+            autocomplete: true
+        }]
+    }
+
+    fn checks(&self) -> CommandChecks { OWNERS_ONLY }
+
+    async fn run(&self, ctx: &SlashContext<'_>, _invocation: &Invocation<'_>, args: &HybridArgs) -> SlashResponse {
+        let registry = ctx.get_data::<CommandRegistryData>().await;
+
+        let dry_run = match args.get_string("mode") {
+            Some("dry") => true,
+            Some(mode) => return SlashResponse::new(format!("Unknown mode `{mode}`, expected `dry` or nothing")),
+            None => ctx.config.get_commands_config().is_dry_run()
+        };
+
+        let scopes = get_scopes(&ctx.config);
+
+        if scopes.is_empty() {
+            return SlashResponse::new("No dev guild is set and global commands are disabled, nothing to sync");
         }
-    };
 
-    Ok(())
+        let mut content = if dry_run {
+            "Dry run, nothing was changed:".to_string()
+        } else {
+            "Synced!".to_string()
+        };
+
+        for scope in scopes {
+            match sync_commands(ctx.http, scope, registry.get_slash_commands(), dry_run).await {
+                Ok(plan) => content.push_str(&format!("\n{plan}")),
+                Err(e) => content.push_str(&format!("\nError while syncing {scope} commands: {e}"))
+            }
+        }
+
+        SlashResponse::new(content)
+    }
+
+    async fn autocomplete(&self, _ctx: &SlashContext<'_>, _arg: &str, _value: &str) -> Vec<AutocompleteChoice> {
+        vec![AutocompleteChoice::new("dry", "dry")]
+    }
 }
 
-#[command]
-#[owners_only]
-/// The classic ping-pong
-pub async fn load(ctx: &Context, msg: &Message) -> CommandResult {
-    let msgs_lock = {
-        let data_read = ctx.data.write().await;
-
-        data_read.get::<UserMessageData>().expect("Expected UserMessageData").clone()
-    };
-
-    let _ = match msgs_lock.write().await.load_cache() {
-        Ok(_) => msg.reply(&ctx.http, "Loaded!").await,
-        Err(e) => {
-            let aaa = format!("Error while loading: {e:?}");
-            msg.reply(&ctx.http, &aaa).await
+/// What `/prefix` can do, the first being the default
+const PREFIX_ACTIONS: &[&str] = &["list", "set", "reset"];
+
+pub struct Prefix;
+
+#[async_trait]
+impl HybridCommand for Prefix {
+    fn name(&self) -> &str { "prefix" }
+
+    fn description(&self) -> &str { "List, set or reset the command prefixes of this server" }
+
+    fn args(&self) -> &'static [ArgSpec] {
+        &[
+            ArgSpec {
+                name: "action",
+                description: "`list`, `set` or `reset`, listing by default",
+                kind: ArgKind::Word,
+                required: false,
+                autocomplete: true
+            },
+            ArgSpec {
+                name: "prefixes",
+                description: "The new prefixes when setting, separated by spaces",
+                kind: ArgKind::Text,
+                required: false,
+                autocomplete: false
+            }
+        ]
+    }
+
+    fn checks(&self) -> CommandChecks {
+        CommandChecks {
+            owners_only: false,
+            guild_only: true,
+            permissions: Permissions::MANAGE_GUILD
         }
-    };
+    }
+
+    async fn run(&self, ctx: &SlashContext<'_>, invocation: &Invocation<'_>, args: &HybridArgs) -> SlashResponse {
+        let guild_id = match invocation.guild_id {
+            Some(guild_id) => guild_id.get(),
+            None => return SlashResponse::new("This command can only be used in a server")
+        };
+
+        let settings_lock = ctx.get_data::<SettingsData>().await;
+
+        match args.get_string("action").unwrap_or(PREFIX_ACTIONS[0]) {
+            "list" => {
+                let content = match settings_lock.read().await.get_guild_prefixes(guild_id) {
+                    Some(prefixes) => format!("This server's prefixes are {}", format_prefixes(prefixes)),
+                    None => format!("This server uses the default prefixes, {}", format_prefixes(ctx.config.get_prefixes()))
+                };
+
+                SlashResponse::new(content)
+            },
+            "set" => {
+                let prefixes = args.get_string("prefixes")
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect::<Vec<String>>();
 
-    Ok(())
-}
\ No newline at end of file
+                if prefixes.is_empty() {
+                    return SlashResponse::new("Please provide at least one prefix");
+                }
+
+                if prefixes.len() > MAX_GUILD_PREFIXES {
+                    return SlashResponse::new(format!("A server can have at most {MAX_GUILD_PREFIXES} prefixes"));
+                }
+
+                if prefixes.iter().any(|prefix| prefix.chars().count() > MAX_PREFIX_LEN) {
+                    return SlashResponse::new(format!("Prefixes can be at most {MAX_PREFIX_LEN} characters long"));
+                }
+
+                let result = settings_lock.write().await.set_guild_prefixes(guild_id, Some(prefixes.clone()));
+
+                match result {
+                    Ok(_) => SlashResponse::new(format!("Prefixes set to {}", format_prefixes(&prefixes))),
+                    Err(e) => SlashResponse::new(format!("Error while setting prefixes: {e:?}"))
+                }
+            },
+            "reset" => {
+                let result = settings_lock.write().await.set_guild_prefixes(guild_id, None);
+
+                match result {
+                    Ok(_) => SlashResponse::new("Prefixes reset to the defaults"),
+                    Err(e) => SlashResponse::new(format!("Error while resetting prefixes: {e:?}"))
+                }
+            },
+            action => SlashResponse::new(format!("Unknown action `{action}`, expected `list`, `set` or `reset`"))
+        }
+    }
+
+    async fn autocomplete(&self, _ctx: &SlashContext<'_>, arg: &str, value: &str) -> Vec<AutocompleteChoice> {
+        if arg != "action" {
+            return Vec::new();
+        }
+
+        PREFIX_ACTIONS.iter()
+            .filter(|action| action.starts_with(value))
+            .map(|action| AutocompleteChoice::new(*action, *action))
+            .collect()
+    }
+}
+
+fn format_prefixes(prefixes: &[String]) -> String {
+    prefixes.iter()
+        .map(|prefix| format!("`{prefix}`"))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -0,0 +1,354 @@
+//! Runs the `/cat` and `/dog` fetches against a local mock server,
+//! by pointing their configs at it instead of the real APIs
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bot_data::config::{BreedListFormat, BreedsConfig, HttpConfig, ImageProviderConfig};
+use commands::fetch_client::FetchClient;
+use commands::fetch_error::FetchError;
+use commands::image_pool::{CircuitBreaker, FallbackImageProvider, ImagePool};
+use commands::image_provider::{Breed, ImageProvider, JsonImageProvider};
+use commands::slash_image::ImageCommand;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const CAT_PATH: &str = "/v1/images/search";
+const DOG_PATH: &str = "/api/breeds/image/random";
+
+/// A client with a 1 second timeout and no wait between retries
+fn client(max_retries: u32) -> FetchClient {
+    let config = format!("timeout_secs = 1\nmax_retries = {max_retries}\nretry_backoff_ms = 0");
+    let config = toml::from_str::<HttpConfig>(&config).expect("Expected valid http config");
+
+    FetchClient::from_config(&config).expect("Expected a client")
+}
+
+fn cat_provider(server: &MockServer) -> JsonImageProvider {
+    let breeds = BreedsConfig::new(
+        &format!("{}/v1/breeds", server.uri()),
+        BreedListFormat::List,
+        &format!("{}{CAT_PATH}?breed_ids={{breed}}", server.uri())
+    );
+
+    JsonImageProvider::new(
+        ImageProviderConfig::new("cat", &format!("{}{CAT_PATH}", server.uri()), "/0/url").with_breeds(breeds)
+    )
+}
+
+fn dog_provider(server: &MockServer) -> JsonImageProvider {
+    let breeds = BreedsConfig::new(
+        &format!("{}/api/breeds/list/all", server.uri()),
+        BreedListFormat::Map,
+        &format!("{}/api/breed/{{breed}}/images/random", server.uri())
+    ).with_list_pointer("/message");
+
+    JsonImageProvider::new(
+        ImageProviderConfig::new("dog", &format!("{}{DOG_PATH}", server.uri()), "/message").with_breeds(breeds)
+    )
+}
+
+async fn mock(server: &MockServer, route: &str, response: ResponseTemplate) {
+    Mock::given(method("GET"))
+        .and(path(route))
+        .respond_with(response)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn cat_success() {
+    let server = MockServer::start().await;
+    let body = r#"[{"id":"abc","url":"https://cdn2.thecatapi.com/images/abc.jpg","width":500,"height":400}]"#;
+    mock(&server, CAT_PATH, ResponseTemplate::new(200).set_body_string(body)).await;
+
+    let image = cat_provider(&server).fetch_image(&client(0), None).await.expect("Expected an image");
+
+    assert_eq!(image.url, "https://cdn2.thecatapi.com/images/abc.jpg");
+    assert_eq!(image.breed, None);
+}
+
+#[tokio::test]
+async fn dog_success() {
+    let server = MockServer::start().await;
+    let body = r#"{"message":"https://images.dog.ceo/breeds/akita/1.jpg","status":"success"}"#;
+    mock(&server, DOG_PATH, ResponseTemplate::new(200).set_body_string(body)).await;
+
+    let image = dog_provider(&server).fetch_image(&client(0), None).await.expect("Expected an image");
+
+    assert_eq!(image.url, "https://images.dog.ceo/breeds/akita/1.jpg");
+}
+
+#[tokio::test]
+async fn cat_empty_array() {
+    let server = MockServer::start().await;
+    mock(&server, CAT_PATH, ResponseTemplate::new(200).set_body_string("[]")).await;
+
+    let result = cat_provider(&server).fetch_image(&client(0), None).await;
+
+    assert!(matches!(result, Err(FetchError::EmptyResponse)));
+}
+
+#[tokio::test]
+async fn dog_empty_body() {
+    let server = MockServer::start().await;
+    mock(&server, DOG_PATH, ResponseTemplate::new(200)).await;
+
+    let result = dog_provider(&server).fetch_image(&client(0), None).await;
+
+    assert!(matches!(result, Err(FetchError::EmptyResponse)));
+}
+
+#[tokio::test]
+async fn cat_bad_json() {
+    let server = MockServer::start().await;
+    mock(&server, CAT_PATH, ResponseTemplate::new(200).set_body_string("[{\"url\": ")).await;
+
+    let result = cat_provider(&server).fetch_image(&client(0), None).await;
+
+    assert!(matches!(result, Err(FetchError::ParseError(name, _)) if name == "cat"));
+}
+
+#[tokio::test]
+async fn dog_error_message_is_not_an_image() {
+    let server = MockServer::start().await;
+    let body = r#"{"message":"Breed not found (master breed does not exist)","status":"error","code":404}"#;
+    mock(&server, DOG_PATH, ResponseTemplate::new(200).set_body_string(body)).await;
+
+    let result = dog_provider(&server).fetch_image(&client(0), None).await;
+
+    assert!(matches!(result, Err(FetchError::MalformedResponse(_))));
+}
+
+#[tokio::test]
+async fn cat_timeout() {
+    let server = MockServer::start().await;
+    let response = ResponseTemplate::new(200)
+        .set_body_string(r#"[{"url":"https://cdn2.thecatapi.com/images/abc.jpg"}]"#)
+        .set_delay(Duration::from_secs(3));
+    mock(&server, CAT_PATH, response).await;
+
+    let result = cat_provider(&server).fetch_image(&client(0), None).await;
+
+    assert!(matches!(result, Err(FetchError::Timeout(_))));
+}
+
+#[tokio::test]
+async fn dog_server_error_is_retried() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path(DOG_PATH))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let result = dog_provider(&server).fetch_image(&client(2), None).await;
+
+    assert!(matches!(result, Err(FetchError::StatusError(status)) if status.as_u16() == 500));
+}
+
+#[tokio::test]
+async fn cat_recovers_after_rate_limit() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path(CAT_PATH))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock(&server, CAT_PATH, ResponseTemplate::new(200).set_body_string(r#"[{"url":"https://cdn2.thecatapi.com/images/abc.jpg"}]"#)).await;
+
+    let image = cat_provider(&server).fetch_image(&client(1), None).await.expect("Expected an image");
+
+    assert_eq!(image.url, "https://cdn2.thecatapi.com/images/abc.jpg");
+}
+
+#[tokio::test]
+async fn client_error_is_not_retried() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path(CAT_PATH))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let result = cat_provider(&server).fetch_image(&client(2), None).await;
+
+    assert!(matches!(result, Err(FetchError::StatusError(status)) if status.as_u16() == 404));
+}
+
+#[tokio::test]
+async fn cat_breed_image() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path(CAT_PATH))
+        .and(query_param("breed_ids", "abys"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"[{"url":"https://cdn2.thecatapi.com/images/abys.jpg"}]"#))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let breed = Breed { id: "abys".to_string(), name: "Abyssinian".to_string() };
+    let image = cat_provider(&server).fetch_image(&client(0), Some(&breed)).await.expect("Expected an image");
+
+    assert_eq!(image.url, "https://cdn2.thecatapi.com/images/abys.jpg");
+    assert_eq!(image.breed.as_deref(), Some("Abyssinian"));
+}
+
+#[tokio::test]
+async fn dog_breeds_are_cached() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/api/breeds/list/all"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"message":{"akita":[],"hound":["afghan"]},"status":"success"}"#))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let provider = dog_provider(&server);
+
+    provider.refresh_breeds(&client(0)).await.expect("Expected a refresh");
+
+    let breeds = provider.get_breeds().await.expect("Expected breeds");
+    let again = provider.get_breeds().await.expect("Expected breeds");
+
+    let ids = breeds.iter().map(|breed| breed.id.as_str()).collect::<Vec<&str>>();
+
+    assert_eq!(ids, vec!["hound/afghan", "akita", "hound"]);
+    assert_eq!(breeds, again);
+}
+
+#[tokio::test]
+async fn breeds_are_not_fetched_on_read() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/breeds"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"[{"id":"abys","name":"Abyssinian"}]"#))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let result = cat_provider(&server).get_breeds().await;
+
+    assert!(matches!(result, Err(FetchError::NotFetched(_))));
+}
+
+#[tokio::test]
+async fn failed_refresh_keeps_old_breeds() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/breeds"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"[{"id":"abys","name":"Abyssinian"}]"#))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    mock(&server, "/v1/breeds", ResponseTemplate::new(500)).await;
+
+    let provider = cat_provider(&server);
+    let client = client(0);
+
+    provider.refresh_breeds(&client).await.expect("Expected a refresh");
+
+    let result = provider.refresh_breeds(&client).await;
+    let breeds = provider.get_breeds().await.expect("Expected the old breeds");
+
+    assert!(matches!(result, Err(FetchError::StatusError(_))));
+    assert_eq!(breeds.as_slice(), &[Breed { id: "abys".to_string(), name: "Abyssinian".to_string() }]);
+}
+
+#[tokio::test]
+async fn breed_refresh_stops_with_its_command() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/api/breeds/list/all"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"message":{"akita":[]},"status":"success"}"#))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let command = Arc::new(ImageCommand::new("Dogs".to_string(), Box::new(dog_provider(&server))));
+    let handle = command.spawn_breed_refresh(Arc::new(client(0))).expect("Expected a refresh task");
+
+    // the first refresh happens straight away
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let other = command.clone();
+    drop(command);
+
+    assert!(!handle.is_finished());
+
+    drop(other);
+
+    tokio::time::timeout(Duration::from_secs(1), handle).await
+        .expect("Expected the task to stop")
+        .expect("Expected the task not to panic");
+}
+
+#[tokio::test]
+async fn fallback_serves_pooled_image_when_down() {
+    let server = MockServer::start().await;
+    let body = r#"{"message":"https://images.dog.ceo/breeds/akita/1.jpg","status":"success"}"#;
+
+    Mock::given(method("GET"))
+        .and(path(DOG_PATH))
+        .respond_with(ResponseTemplate::new(200).set_body_string(body))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    // after the first request the upstream only fails, and
+    // is skipped once it has failed once
+    Mock::given(method("GET"))
+        .and(path(DOG_PATH))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let provider = FallbackImageProvider::new(
+        Box::new(dog_provider(&server)),
+        ImagePool::new(10, Vec::new()),
+        CircuitBreaker::new(1, Duration::from_secs(60))
+    );
+
+    let client = client(0);
+
+    for _ in 0..3 {
+        let image = provider.fetch_image(&client, None).await.expect("Expected an image");
+
+        assert_eq!(image.url, "https://images.dog.ceo/breeds/akita/1.jpg");
+    }
+}
+
+#[tokio::test]
+async fn fallback_without_images_is_unavailable() {
+    let server = MockServer::start().await;
+    mock(&server, CAT_PATH, ResponseTemplate::new(500)).await;
+
+    let provider = FallbackImageProvider::new(
+        Box::new(cat_provider(&server)),
+        ImagePool::new(10, Vec::new()),
+        CircuitBreaker::new(1, Duration::from_secs(60))
+    );
+
+    let client = client(0);
+
+    let first = provider.fetch_image(&client, None).await;
+    let second = provider.fetch_image(&client, None).await;
+
+    assert!(matches!(first, Err(FetchError::StatusError(_))));
+    assert!(matches!(second, Err(FetchError::Unavailable(name)) if name == "cat"));
+}